@@ -0,0 +1,250 @@
+use futures_util::TryStreamExt;
+use mongodb::{
+    Database,
+    bson::{self, Bson, Document, doc},
+};
+use serde::de::DeserializeOwned;
+use std::marker::PhantomData;
+
+use crate::{Deleted, Field, Model, Res, Sort, Tumongo};
+
+/// aggregation pipeline over model `M`, see the generated `aggregate()`.
+///
+/// soft deleted docs are filtered out before the first stage
+pub struct Aggregate<M> {
+    stages: Vec<Document>,
+    /// set by [crate::TenantScope::aggregate], lookups into tenant models match on it
+    tenant: Option<Bson>,
+    /// a tenant model looked up without a tenant, `run` refuses the pipeline
+    unscoped_lookup: Option<String>,
+    _model: PhantomData<fn() -> M>,
+}
+
+impl<M: Model> Default for Aggregate<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: Model> Aggregate<M> {
+    pub fn new() -> Self {
+        Self {
+            stages: vec![],
+            tenant: None,
+            unscoped_lookup: None,
+            _model: PhantomData,
+        }
+    }
+
+    pub(crate) fn scoped(mut self, tenant: Bson) -> Self {
+        self.tenant = Some(tenant);
+        self
+    }
+
+    /// raw stage for what the builder doesn't cover
+    pub fn stage(mut self, stage: Document) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    /// takes a Document or a tumongo::Filter
+    pub fn match_(self, filter: impl Into<Document>) -> Self {
+        self.stage(doc! {"$match": filter.into()})
+    }
+
+    /// `id` is the group key, e.g `fields::company_id.path()`,
+    /// `acc` the accumulators like `{"total": {"$sum": 1}}`
+    pub fn group(self, id: impl Into<Bson>, acc: Document) -> Self {
+        let mut group = doc! {"_id": id.into()};
+        group.extend(acc);
+        self.stage(doc! {"$group": group})
+    }
+
+    pub fn sort(self, sort: Sort<M>) -> Self {
+        self.stage(doc! {"$sort": sort.into_doc()})
+    }
+
+    pub fn skip(self, skip: u64) -> Self {
+        self.stage(doc! {"$skip": skip as i64})
+    }
+
+    pub fn limit(self, limit: i64) -> Self {
+        self.stage(doc! {"$limit": limit})
+    }
+
+    pub fn project(self, projection: Document) -> Self {
+        self.stage(doc! {"$project": projection})
+    }
+
+    pub fn unwind<T>(self, field: Field<M, Vec<T>>) -> Self {
+        self.stage(doc! {"$unwind": field.path()})
+    }
+
+    /// joins the docs of model `O` whose `foreign` equals our `local` into `as_`.
+    /// tenant models `O` are only joined within the tenant of a [crate::TenantScope::aggregate]
+    pub fn lookup<O: Model, A, B>(mut self, local: Field<M, A>, foreign: Field<O, B>, as_: &str) -> Self {
+        let Some(field) = O::tenant_field() else {
+            return self.stage(doc! {"$lookup": {
+                "from": O::coll_name(),
+                "localField": local.name(),
+                "foreignField": foreign.name(),
+                "as": as_,
+            }});
+        };
+        let Some(tenant) = self.tenant.clone() else {
+            self.unscoped_lookup.get_or_insert(O::coll_name());
+            return self;
+        };
+        self.stage(doc! {"$lookup": {
+            "from": O::coll_name(),
+            "let": {"local": format!("${}", local.name())},
+            "pipeline": [{"$match": {
+                "$expr": {"$eq": [format!("${}", foreign.name()), "$$local"]},
+                field: tenant,
+            }}],
+            "as": as_,
+        }})
+    }
+
+    /// runs each named sub pipeline on the same input, repeated calls add to one $facet stage
+    pub fn facet(mut self, name: &str, sub: Aggregate<M>) -> Self {
+        if let Some(coll_name) = sub.unscoped_lookup {
+            self.unscoped_lookup.get_or_insert(coll_name);
+        }
+        let sub: Vec<Bson> = sub.stages.into_iter().map(Bson::Document).collect();
+        if let Some(Ok(facet)) = self.stages.last_mut().map(|s| s.get_document_mut("$facet")) {
+            facet.insert(name, sub);
+            return self;
+        }
+        self.stage(doc! {"$facet": {name: sub}})
+    }
+
+    /// the stages as sent to the server
+    pub fn pipeline(&self) -> Vec<Document> {
+        let mut pipeline = vec![];
+        if M::soft_delete() {
+            pipeline.push(doc! {"$match": Deleted::Exclude.apply(Document::new())});
+        }
+        pipeline.extend(self.stages.iter().cloned());
+        pipeline
+    }
+
+    /// fails for tenant models unless the first stage is a `$match` on the tenant field,
+    /// and for lookups into tenant models outside a [crate::TenantScope]
+    pub async fn run(self, db: &Database) -> Res<Vec<Document>> {
+        if let Some(coll_name) = &self.unscoped_lookup {
+            return Err(format!("[{coll_name}] lookup needs a tenant, use TenantScope::aggregate").into());
+        }
+        let first_match = self.stages.first().and_then(|s| s.get_document("$match").ok());
+        Tumongo::check_tenant::<M>(first_match.unwrap_or(&Document::new()))?;
+        let doks = Tumongo::coll::<Document>(db, &M::coll_name())?
+            .aggregate(self.pipeline())
+            .await?
+            .try_collect()
+            .await?;
+        Ok(doks)
+    }
+
+    /// deserialize the results as `Out`
+    pub fn into_typed<Out: DeserializeOwned>(self) -> TypedAggregate<M, Out> {
+        TypedAggregate {
+            inner: self,
+            _out: PhantomData,
+        }
+    }
+}
+
+/// [Aggregate] whose results come out as `Out`
+pub struct TypedAggregate<M, Out> {
+    inner: Aggregate<M>,
+    _out: PhantomData<fn() -> Out>,
+}
+
+impl<M: Model, Out: DeserializeOwned> TypedAggregate<M, Out> {
+    pub fn pipeline(&self) -> Vec<Document> {
+        self.inner.pipeline()
+    }
+
+    pub async fn run(self, db: &Database) -> Res<Vec<Out>> {
+        let doks = self.inner.run(db).await?;
+        let mut items = Vec::with_capacity(doks.len());
+        for dok in doks {
+            items.push(bson::from_document(dok)?);
+        }
+        Ok(items)
+    }
+}
+
+impl Tumongo {
+    pub fn aggregate<M: Model>() -> Aggregate<M> {
+        Aggregate::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use mongodb::{ClientSession, bson::oid::ObjectId};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    struct Post {
+        title: String,
+    }
+
+    #[async_trait]
+    impl Model for Post {
+        fn coll_name() -> String {
+            "post".into()
+        }
+        fn soft_delete() -> bool {
+            true
+        }
+        fn id(&self) -> Option<ObjectId> {
+            None
+        }
+        fn set_id(&mut self, _id: ObjectId) {}
+        fn to_doc(&self) -> Document {
+            bson::to_document(self).unwrap()
+        }
+        fn take_snapshot(&mut self) {}
+        async fn save(&mut self, _db: &Database) -> Res<()> {
+            unimplemented!()
+        }
+        async fn delete(&mut self, _db: &Database) -> Res<()> {
+            unimplemented!()
+        }
+        async fn save_with_session(&mut self, _db: &Database, _sess: &mut ClientSession) -> Res<()> {
+            unimplemented!()
+        }
+        async fn delete_with_session(&mut self, _db: &Database, _sess: &mut ClientSession) -> Res<()> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn soft_deleted_docs_are_matched_out_first() {
+        let pipeline = Aggregate::<Post>::new().limit(5).pipeline();
+        assert_eq!(pipeline, vec![doc! {"$match": {"deleted_at": null}}, doc! {"$limit": 5_i64}]);
+    }
+
+    #[test]
+    fn facets_share_one_stage() {
+        let agg = Aggregate::<Post>::new()
+            .facet("top", Aggregate::new().limit(3))
+            .facet("total", Aggregate::new().stage(doc! {"$count": "n"}));
+        assert_eq!(
+            agg.pipeline()[1..],
+            [doc! {"$facet": {
+                "top": [{"$limit": 3_i64}],
+                "total": [{"$count": "n"}],
+            }}]
+        );
+
+        // a stage in between starts a new $facet
+        let agg = agg.limit(1).facet("rest", Aggregate::new().skip(1));
+        assert_eq!(agg.pipeline().len(), 4);
+        assert_eq!(agg.pipeline()[3], doc! {"$facet": {"rest": [{"$skip": 1_i64}]}});
+    }
+}
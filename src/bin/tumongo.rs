@@ -0,0 +1,11 @@
+//! stock `tumongo` cli, only knows the models registered in this build.
+//! apps with models and migrations should ship their own bin, see [tumongo::cli]
+#[tokio::main]
+async fn main() {
+    // no models here, the relation and model commands will say so
+    tumongo::register!();
+    if let Err(err) = tumongo::cli::Cli::new().run_env().await {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,410 @@
+use futures_util::TryStreamExt;
+use mongodb::{
+    Database,
+    bson::{self, Bson, Document, doc, oid::ObjectId},
+    error::ErrorKind,
+    options::{DeleteOneModel, InsertOneModel, UpdateOneModel, WriteModel},
+    results::VerboseBulkWriteResult,
+};
+use std::collections::HashMap;
+
+use crate::{Conflict, DELETED_AT, HistoryOp, Model, Res, Tumongo};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+enum BulkOp<'a, M> {
+    Insert(&'a mut M),
+    Update(&'a mut M),
+    Save(&'a mut M),
+    Delete(&'a mut M),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// outcome of one [BulkWrite] op
+#[derive(Debug)]
+pub struct BulkItemResult {
+    /// position of the op in the BulkWrite
+    pub index: usize,
+    pub id: Option<ObjectId>,
+    pub error: Option<BoxError>,
+}
+
+#[derive(Debug, Default)]
+pub struct BulkResult {
+    /// one per op that ran, an ordered write stops after the first failure
+    pub items: Vec<BulkItemResult>,
+}
+
+impl BulkResult {
+    pub fn ok_count(&self) -> usize {
+        self.items.iter().filter(|r| r.error.is_none()).count()
+    }
+    pub fn failed(&self) -> impl Iterator<Item = &BulkItemResult> {
+        self.items.iter().filter(|r| r.error.is_some())
+    }
+    pub fn is_ok(&self) -> bool {
+        self.items.iter().all(|r| r.error.is_none())
+    }
+}
+
+/// one op on its way to the server
+struct Item<'a, M> {
+    index: usize,
+    kind: Kind,
+    item: &'a mut M,
+    /// the doc as it's stored after the write
+    dok: Document,
+    error: Option<BoxError>,
+    /// the version an update expects, for the Conflict
+    version: Option<i64>,
+    /// position in the driver's bulk write, None if nothing is sent
+    sent: Option<usize>,
+}
+
+/// mixed inserts/updates/deletes of model `M` sent as one driver bulk write (MongoDB 8.0+).
+///
+/// like `save` and `delete`: timestamps, unique checks, sequences, versions and history apply and
+/// ids are written back. unique checks run as one query per unique field before the write.
+/// ops failing those checks fail on their own, ordered writes stop there. a version conflict
+/// only shows in the update's result, so it fails that op without stopping an ordered write.
+/// deletes of docs with registered fk children are refused, use the model's `delete` for cascades
+pub struct BulkWrite<'a, M> {
+    ops: Vec<BulkOp<'a, M>>,
+    ordered: bool,
+}
+
+impl<M: Model> Default for BulkWrite<'_, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, M: Model> BulkWrite<'a, M> {
+    /// ordered by default
+    pub fn new() -> Self {
+        Self {
+            ops: vec![],
+            ordered: true,
+        }
+    }
+    /// the driver's `ordered`: ordered writes stop at the first failure, unordered ones carry on
+    pub fn ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+        self
+    }
+    /// fails if the item already has an id
+    pub fn insert(mut self, item: &'a mut M) -> Self {
+        self.ops.push(BulkOp::Insert(item));
+        self
+    }
+    /// fails if the item has no id
+    pub fn update(mut self, item: &'a mut M) -> Self {
+        self.ops.push(BulkOp::Update(item));
+        self
+    }
+    /// insert or update depending on the id
+    pub fn save(mut self, item: &'a mut M) -> Self {
+        self.ops.push(BulkOp::Save(item));
+        self
+    }
+    pub fn delete(mut self, item: &'a mut M) -> Self {
+        self.ops.push(BulkOp::Delete(item));
+        self
+    }
+
+    /// errors are per item, the whole run only fails when the batch can't be checked or sent
+    pub async fn run(self, db: &Database) -> Res<BulkResult> {
+        let coll_name = M::coll_name();
+        let coll = Tumongo::coll::<Document>(db, &coll_name)?;
+        let mut items: Vec<Item<'a, M>> = self
+            .ops
+            .into_iter()
+            .enumerate()
+            .map(|(index, op)| {
+                let (kind, item, error): (_, _, Option<BoxError>) = match op {
+                    BulkOp::Insert(item) => match item.id() {
+                        Some(_id) => (Kind::Insert, item, Some(format!("[{index}] insert of {_id} which already has an id").into())),
+                        None => (Kind::Insert, item, None),
+                    },
+                    BulkOp::Update(item) => match item.id() {
+                        Some(_) => (Kind::Update, item, None),
+                        None => (Kind::Update, item, Some(format!("[{index}] update of an item without an id").into())),
+                    },
+                    BulkOp::Save(item) if item.id().is_some() => (Kind::Update, item, None),
+                    BulkOp::Save(item) => (Kind::Insert, item, None),
+                    BulkOp::Delete(item) => match item.id() {
+                        Some(_) => (Kind::Delete, item, None),
+                        None => (Kind::Delete, item, Some(format!("[{index}] delete of an item without an id").into())),
+                    },
+                };
+                Item {
+                    index,
+                    kind,
+                    dok: item.to_doc(),
+                    item,
+                    error,
+                    version: None,
+                    sent: None,
+                }
+            })
+            .collect();
+
+        if items.iter().any(|it| it.kind == Kind::Delete) {
+            let children: Vec<_> = Tumongo::fk_fields()
+                .get(&coll_name)
+                .map(|fks| fks.iter().map(|fk| fk.coll.as_str()).collect())
+                .unwrap_or_default();
+            if !children.is_empty() {
+                for it in items.iter_mut().filter(|it| it.kind == Kind::Delete && it.error.is_none()) {
+                    it.error = Some(format!("[{}] delete of {coll_name} cascades to {children:?}, delete it on its own", it.index).into());
+                }
+            }
+        }
+        Self::check_unique(&coll, &coll_name, &mut items).await?;
+
+        // an ordered write never gets past the first failed op
+        let run_len = match self.ordered {
+            true => items.iter().position(|it| it.error.is_some()).map_or(items.len(), |i| i + 1),
+            false => items.len(),
+        };
+        items.truncate(run_len);
+
+        let ids: Vec<ObjectId> = items
+            .iter()
+            .filter(|it| it.error.is_none() && it.kind != Kind::Insert)
+            .filter_map(|it| it.item.id())
+            .collect();
+        let mut befores = HashMap::new();
+        if M::history() && !ids.is_empty() {
+            let mut cursor = coll.find(doc! {"_id": {"$in": &ids}}).await?;
+            while let Some(dok) = cursor.try_next().await? {
+                if let Ok(_id) = dok.get_object_id("_id") {
+                    befores.insert(_id, dok);
+                }
+            }
+        }
+
+        let model_db = Tumongo::route_db(db, &coll_name)?;
+        let mut sess = model_db.client().start_session().await?;
+        let namespace = coll.namespace();
+        let now = bson::DateTime::now();
+        let mut models: Vec<WriteModel> = vec![];
+        for it in items.iter_mut().filter(|it| it.error.is_none()) {
+            let model: WriteModel = match it.kind {
+                Kind::Insert => {
+                    let _id = ObjectId::new();
+                    it.dok.insert("_id", _id);
+                    it.dok.insert("created_at", now);
+                    it.dok.insert("updated_at", now);
+                    for (field, name, scope) in M::sequence_fields() {
+                        let scope = scope.and_then(|scope| it.dok.get(scope).cloned());
+                        // like save, a 0 gets the next number
+                        let val = match it.dok.get(field) {
+                            Some(Bson::Int32(0)) => Bson::Int32(Tumongo::next_sequence(&model_db, &mut sess, name, scope).await?.try_into()?),
+                            Some(Bson::Int64(0)) => Bson::Int64(Tumongo::next_sequence(&model_db, &mut sess, name, scope).await?),
+                            _ => continue,
+                        };
+                        it.dok.insert(field, val);
+                    }
+                    InsertOneModel::builder().namespace(namespace.clone()).document(it.dok.clone()).build().into()
+                }
+                Kind::Update => {
+                    let (mut set, unset) = it.item.changes();
+                    set.remove("_id");
+                    // unchanged, nothing to send
+                    if set.is_empty() && unset.is_empty() {
+                        continue;
+                    }
+                    set.insert("updated_at", now);
+                    let mut filter = doc! {"_id": it.item.id()};
+                    if let Some(field) = M::version_field() {
+                        let (current, next) = match it.dok.get(field) {
+                            Some(Bson::Int32(v)) => (i64::from(*v), Bson::Int32(v + 1)),
+                            Some(Bson::Int64(v)) => (*v, Bson::Int64(v + 1)),
+                            _ => (0, Bson::Int64(1)),
+                        };
+                        filter.insert(field, it.dok.get(field).cloned().unwrap_or(Bson::Null));
+                        set.insert(field, next);
+                        it.version = Some(current);
+                    }
+                    for k in unset.keys() {
+                        it.dok.remove(k);
+                    }
+                    it.dok.extend(set.clone());
+                    let mut update = doc! {"$set": set};
+                    if !unset.is_empty() {
+                        update.insert("$unset", unset);
+                    }
+                    UpdateOneModel::builder()
+                        .namespace(namespace.clone())
+                        .filter(filter)
+                        .update(update)
+                        .build()
+                        .into()
+                }
+                Kind::Delete if M::soft_delete() => {
+                    it.dok.insert(DELETED_AT, now);
+                    UpdateOneModel::builder()
+                        .namespace(namespace.clone())
+                        .filter(doc! {"_id": it.item.id()})
+                        .update(doc! {"$set": {DELETED_AT: now}})
+                        .build()
+                        .into()
+                }
+                Kind::Delete => DeleteOneModel::builder()
+                    .namespace(namespace.clone())
+                    .filter(doc! {"_id": it.item.id()})
+                    .build()
+                    .into(),
+            };
+            it.sent = Some(models.len());
+            models.push(model);
+        }
+
+        let (result, write_errors) = match models.is_empty() {
+            true => (None, HashMap::new()),
+            false => {
+                let mut action = model_db
+                    .client()
+                    .bulk_write(models)
+                    .ordered(self.ordered)
+                    .verbose_results();
+                if let Some(wc) = coll.write_concern() {
+                    action = action.write_concern(wc.clone());
+                }
+                match action.await {
+                    Ok(result) => (Some(result), HashMap::new()),
+                    Err(err) => match *err.kind {
+                        ErrorKind::BulkWrite(bulk_err) => {
+                            let result = match bulk_err.partial_result {
+                                Some(mongodb::error::PartialBulkWriteResult::Verbose(result)) => Some(result),
+                                _ => None,
+                            };
+                            (result, bulk_err.write_errors)
+                        }
+                        _ => return Err(err.into()),
+                    },
+                }
+            }
+        };
+
+        let mut res = BulkResult::default();
+        for mut it in items {
+            if let Some(k) = it.sent {
+                if let Some(err) = write_errors.get(&k) {
+                    it.error = Some(format!("[{}] {}", it.index, err.message).into());
+                } else if !Self::ran(result.as_ref(), k) {
+                    // an ordered write stopped before it
+                    continue;
+                } else if let Some(version) = it.version
+                    && result.as_ref().and_then(|r| r.update_results.get(&k)).is_some_and(|r| r.matched_count == 0)
+                {
+                    it.error = Some(
+                        Conflict {
+                            coll: coll_name.clone(),
+                            id: it.item.id().expect("updates have an id"),
+                            version,
+                        }
+                        .into(),
+                    );
+                } else {
+                    let before = it.item.id().and_then(|_id| befores.remove(&_id));
+                    it.error = Self::finish(db, &mut sess, &coll_name, &mut it, before).await.err();
+                }
+            }
+            let id = match it.kind {
+                Kind::Insert => it.dok.get_object_id("_id").ok().filter(|_| it.error.is_none()),
+                _ => it.item.id(),
+            };
+            res.items.push(BulkItemResult {
+                index: it.index,
+                id,
+                error: it.error,
+            });
+        }
+        Ok(res)
+    }
+
+    /// whether the driver reports a result for write `k`
+    fn ran(result: Option<&VerboseBulkWriteResult>, k: usize) -> bool {
+        result.is_some_and(|r| {
+            r.insert_results.contains_key(&k) || r.update_results.contains_key(&k) || r.delete_results.contains_key(&k)
+        })
+    }
+
+    /// writes the stored doc back into the item and records history
+    async fn finish(
+        db: &Database,
+        sess: &mut mongodb::ClientSession,
+        coll_name: &str,
+        it: &mut Item<'_, M>,
+        before: Option<Document>,
+    ) -> Res<()> {
+        let _id = it.dok.get_object_id("_id")?;
+        if M::history() {
+            let (op, after) = match it.kind {
+                Kind::Insert => (HistoryOp::Insert, it.dok.clone()),
+                Kind::Update => (HistoryOp::Update, it.dok.clone()),
+                Kind::Delete if M::soft_delete() => (HistoryOp::Delete, it.dok.clone()),
+                Kind::Delete => (HistoryOp::Delete, Document::new()),
+            };
+            Tumongo::record_history(db, sess, coll_name, &_id, op, &before.unwrap_or_default(), &after).await?;
+        }
+        if it.kind != Kind::Delete || M::soft_delete() {
+            *it.item = M::from_doc(it.dok.clone())?;
+        }
+        Ok(())
+    }
+
+    /// fails the items clashing with a stored doc or an earlier item on a unique field
+    async fn check_unique(coll: &mongodb::Collection<Document>, coll_name: &str, items: &mut [Item<'a, M>]) -> Res<()> {
+        let mut checks: Vec<(&str, Option<&str>)> = M::unique_fields().into_iter().map(|f| (f, None)).collect();
+        checks.extend(M::unique_if_same_fields().into_iter().map(|(f, same)| (f, Some(same))));
+        for (field, same) in checks {
+            let key = |dok: &Document| -> Option<Document> {
+                let mut key = doc! {field: dok.get(field)?.clone()};
+                if let Some(same) = same {
+                    key.insert(same, dok.get(same).cloned().unwrap_or(Bson::Null));
+                }
+                Some(key)
+            };
+            let checked: Vec<usize> = (0..items.len())
+                .filter(|&i| items[i].error.is_none() && items[i].kind != Kind::Delete)
+                .collect();
+            let keys: Vec<Document> = checked.iter().filter_map(|&i| key(&items[i].dok)).collect();
+            if keys.is_empty() {
+                continue;
+            }
+            let mut projection = doc! {field: 1};
+            if let Some(same) = same {
+                projection.insert(same, 1);
+            }
+            let stored: Vec<Document> = coll.find(doc! {"$or": keys}).projection(projection).await?.try_collect().await?;
+
+            let mut seen: Vec<Document> = vec![];
+            for i in checked {
+                let Some(k) = key(&items[i].dok) else {
+                    continue;
+                };
+                let id = items[i].item.id();
+                let taken = stored.iter().any(|dok| {
+                    dok.get_object_id("_id").ok() != id && key(dok).as_ref() == Some(&k)
+                });
+                if taken || seen.contains(&k) {
+                    items[i].error = Some(
+                        format!("UNIQUE FIELD ERROR: another doc with {k} already exists in {coll_name} collection.").into(),
+                    );
+                } else {
+                    seen.push(k);
+                }
+            }
+        }
+        Ok(())
+    }
+}
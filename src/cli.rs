@@ -0,0 +1,408 @@
+//! the `tumongo` command line, behind the `cli` feature.
+//!
+//! models and migrations live in the app, so apps ship their own bin:
+//! ```ignore
+//! #[tokio::main]
+//! async fn main() {
+//!     tumongo::register!();
+//!     if let Err(err) = run().await {
+//!         eprintln!("{err}");
+//!         std::process::exit(1);
+//!     }
+//! }
+//!
+//! async fn run() -> tumongo::Res<()> {
+//!     tumongo::cli::Cli::new().model::<Person>().migration(AddAlias)?.run_env().await
+//! }
+//! ```
+use futures_util::future::BoxFuture;
+use mongodb::{
+    Database,
+    bson::{Bson, Document, doc, oid::ObjectId},
+    options::{ValidationAction, ValidationLevel},
+};
+use once_cell::sync::OnceCell;
+use std::{collections::HashSet, fs};
+
+use crate::{
+    ConnOpts, DEFAULT_CONN, FK_FIELDS, FkFieldMap, IndexSync, Migration, Model, REF_FIELDS, Res, Tumongo,
+    TumongoConfig, ValidatorOpts,
+};
+
+const USAGE: &str = "usage: tumongo [--config tumongo.toml] [--conn name] <command>
+
+commands:
+  migrate                     apply pending migrations
+  rollback [n]                revert the last n migrations (1)
+  status                      list migrations
+  indexes sync [--drop]       sync declared indexes, --drop removes undeclared ones
+  validators apply            push the models' $jsonSchema validators,
+                              --level off|strict|moderate, --action error|warn
+  check-integrity             list fk/ref fields pointing at missing docs
+  relations graph             print the relations as graphviz dot
+  dump <coll> <id> [file]     write a doc and its fk children as extended json
+  restore <file>              upsert the docs of a dump
+  seed <file>                 upsert {\"coll\": [docs]} from a json file
+
+without --config the connection comes from TumongoConfig::from_env, or MONGO_URL/MONGO_DB";
+
+type SyncIndexesFn = fn(&Database, bool) -> BoxFuture<'_, Res<IndexSync>>;
+type ApplyValidatorFn = for<'a> fn(&'a Database, &'a ValidatorOpts) -> BoxFuture<'a, Res<()>>;
+
+fn sync_model_indexes<M: Model + 'static>(db: &Database, drop: bool) -> BoxFuture<'_, Res<IndexSync>> {
+    Box::pin(Tumongo::sync_indexes::<M>(db, drop))
+}
+
+fn apply_model_validator<'a, M: Model + 'static>(
+    db: &'a Database,
+    opts: &'a ValidatorOpts,
+) -> BoxFuture<'a, Res<()>> {
+    Box::pin(Tumongo::apply_validator::<M>(db, opts))
+}
+
+struct CliModel {
+    coll_name: String,
+    sync_indexes: SyncIndexesFn,
+    apply_validator: ApplyValidatorFn,
+}
+
+#[derive(Default)]
+pub struct Cli {
+    models: Vec<CliModel>,
+}
+
+impl Cli {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// makes `indexes sync` and `validators apply` cover `M`
+    pub fn model<M: Model + 'static>(mut self) -> Self {
+        self.models.push(CliModel {
+            coll_name: M::coll_name(),
+            sync_indexes: sync_model_indexes::<M>,
+            apply_validator: apply_model_validator::<M>,
+        });
+        self
+    }
+
+    /// registers `migration`, fails like [Tumongo::register_migration] for a duplicate version
+    pub fn migration(self, migration: impl Migration + 'static) -> Res<Self> {
+        Tumongo::register_migration(migration)?;
+        Ok(self)
+    }
+
+    /// runs with the process arguments
+    pub async fn run_env(self) -> Res<()> {
+        self.run(std::env::args().skip(1)).await
+    }
+
+    /// runs with `args`, without the program name
+    pub async fn run(self, args: impl IntoIterator<Item = String>) -> Res<()> {
+        let mut config_path = None;
+        let mut conn = None;
+        let mut drop = false;
+        let mut validator_opts = ValidatorOpts::default();
+        let mut pos = vec![];
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => config_path = args.next(),
+                "--conn" => conn = args.next(),
+                "--drop" => drop = true,
+                "--level" => {
+                    validator_opts.level = match args.next().as_deref() {
+                        Some("off") => ValidationLevel::Off,
+                        Some("strict") => ValidationLevel::Strict,
+                        Some("moderate") => ValidationLevel::Moderate,
+                        _ => return Err("--level should be off, strict or moderate".into()),
+                    }
+                }
+                "--action" => {
+                    validator_opts.action = match args.next().as_deref() {
+                        Some("error") => ValidationAction::Error,
+                        Some("warn") => ValidationAction::Warn,
+                        _ => return Err("--action should be error or warn".into()),
+                    }
+                }
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    return Ok(());
+                }
+                _ => pos.push(arg),
+            }
+        }
+        let pos: Vec<&str> = pos.iter().map(|s| s.as_str()).collect();
+        if pos.is_empty() {
+            return Err(USAGE.into());
+        }
+
+        let config = match config_path {
+            Some(path) => TumongoConfig::from_toml_file(path)?,
+            None => Self::env_config()?,
+        };
+        let conns = Tumongo::connect(&config).await?;
+        let db = match &conn {
+            Some(name) => conns.database(name)?.clone(),
+            None => conns.db().clone(),
+        };
+
+        match pos.as_slice() {
+            ["migrate"] => {
+                Self::check_migrations()?;
+                let done = Tumongo::migrate(&db).await?;
+                println!("{} migration(s) applied {done:?}", done.len());
+            }
+            ["rollback"] => self.rollback(&db, "1").await?,
+            ["rollback", n] => self.rollback(&db, n).await?,
+            ["status"] => {
+                for m in Tumongo::migration_status(&db).await? {
+                    let at = m.applied_at.map(|at| at.to_string()).unwrap_or("pending".into());
+                    println!("{} {} {at}", m.version, m.name);
+                }
+            }
+            ["indexes", "sync"] => self.sync_indexes(&db, drop).await?,
+            ["validators", "apply"] => {
+                self.check_models()?;
+                for model in &self.models {
+                    (model.apply_validator)(&db, &validator_opts).await?;
+                    println!("[{}] validator applied", model.coll_name);
+                }
+            }
+            ["check-integrity"] => {
+                Self::check_registry()?;
+                let dangling = Self::check_integrity(&db).await?;
+                if dangling > 0 {
+                    return Err(format!("{dangling} dangling reference(s)").into());
+                }
+                println!("No dangling references");
+            }
+            ["relations", "graph"] => {
+                Self::check_registry()?;
+                println!("{}", Self::relations_graph());
+            }
+            ["dump", coll, id] => {
+                Self::check_registry()?;
+                println!("{}", Self::dump(&db, coll, id).await?);
+            }
+            ["dump", coll, id, file] => {
+                Self::check_registry()?;
+                fs::write(file, Self::dump(&db, coll, id).await?)?;
+                println!("Dumped to {file}");
+            }
+            ["restore", file] => {
+                let n = Self::restore(&db, &fs::read_to_string(file)?).await?;
+                println!("{n} doc(s) restored");
+            }
+            ["seed", file] => {
+                let n = Self::seed(&db, &fs::read_to_string(file)?).await?;
+                println!("{n} doc(s) seeded");
+            }
+            _ => return Err(USAGE.into()),
+        }
+        Ok(())
+    }
+
+    fn env_config() -> Res<TumongoConfig> {
+        match TumongoConfig::from_env() {
+            Ok(config) => Ok(config),
+            Err(err) => {
+                // the older MONGO_URL_LOCAL style setups
+                let url = crate::mongo_url(true).map_err(|_| err)?;
+                let db_name = std::env::var("MONGO_DB").map_err(|_| "MONGO_DB is not set")?;
+                Ok(TumongoConfig::builder().connection(DEFAULT_CONN, ConnOpts::new(&url, &db_name)))
+            }
+        }
+    }
+
+    async fn rollback(&self, db: &Database, n: &str) -> Res<()> {
+        Self::check_migrations()?;
+        let n: usize = n.parse().map_err(|_| format!("Invalid rollback count {n}"))?;
+        let done = Tumongo::rollback(db, n).await?;
+        println!("{} migration(s) reverted {done:?}", done.len());
+        Ok(())
+    }
+
+    fn check_models(&self) -> Res<()> {
+        if self.models.is_empty() {
+            return Err("No models registered, add them with Cli::model".into());
+        }
+        Ok(())
+    }
+
+    fn check_migrations() -> Res<()> {
+        if Tumongo::migrations().is_empty() {
+            return Err("No migrations registered, add them with Cli::migration".into());
+        }
+        Ok(())
+    }
+
+    /// the relation commands only know what `tumongo::register!()` saw
+    fn check_registry() -> Res<()> {
+        let empty = |fields: &OnceCell<FkFieldMap>| fields.get().is_none_or(|f| f.is_empty());
+        if empty(&FK_FIELDS) && empty(&REF_FIELDS) {
+            return Err("No fk or ref fields registered, call tumongo::register!() in the bin defining the models".into());
+        }
+        Ok(())
+    }
+
+    async fn sync_indexes(&self, db: &Database, drop: bool) -> Res<()> {
+        self.check_models()?;
+        for model in &self.models {
+            let res = (model.sync_indexes)(db, drop).await?;
+            println!(
+                "[{}] created {:?}, changed {:?}, dropped {:?}, undeclared {:?}",
+                model.coll_name, res.created, res.changed, res.dropped, res.undeclared
+            );
+        }
+        Ok(())
+    }
+
+    /// (holder coll, field, target coll, on_delete) of every fk and ref field
+    fn relations() -> Vec<(String, String, String, Option<String>)> {
+        let mut rels = vec![];
+        // fk: keyed by the target, item.coll holds the field
+        for (target, items) in Tumongo::fk_fields() {
+            for item in items {
+                rels.push((item.coll.clone(), item.field_name.clone(), target.clone(), item.on_delete.clone()));
+            }
+        }
+        // ref: keyed by the holder, item.coll is the target
+        for (holder, items) in Tumongo::ref_fields() {
+            for item in items {
+                rels.push((holder.clone(), item.field_name.clone(), item.coll.clone(), None));
+            }
+        }
+        rels.sort();
+        rels.dedup();
+        rels
+    }
+
+    /// prints the dangling refs per relation, returns how many there are
+    pub async fn check_integrity(db: &Database) -> Res<usize> {
+        let mut total = 0;
+        for (holder, field, target, _) in Self::relations() {
+            let field = field.as_str();
+            let ids = Tumongo::coll::<Document>(db, &holder)?
+                .distinct(field, doc! {field: {"$ne": Bson::Null}})
+                .await?;
+            if ids.is_empty() {
+                continue;
+            }
+            let existing = Tumongo::coll::<Document>(db, &target)?
+                .distinct("_id", doc! {"_id": {"$in": ids.clone()}})
+                .await?;
+            let missing: Vec<Bson> = ids.into_iter().filter(|id| !existing.contains(id)).collect();
+            if missing.is_empty() {
+                continue;
+            }
+            let n = Tumongo::coll::<Document>(db, &holder)?
+                .count_documents(doc! {field: {"$in": missing.clone()}})
+                .await?;
+            println!("[{holder}.{field} -> {target}] {n} doc(s) point at missing {missing:?}");
+            total += n as usize;
+        }
+        Ok(total)
+    }
+
+    /// the relations as a graphviz digraph, holder -> target
+    pub fn relations_graph() -> String {
+        let mut out = String::from("digraph tumongo {\n");
+        for (holder, field, target, on_delete) in Self::relations() {
+            let label = match on_delete {
+                Some(on_delete) => format!("{field} ({on_delete})"),
+                None => field,
+            };
+            out.push_str(&format!("  \"{holder}\" -> \"{target}\" [label=\"{label}\"];\n"));
+        }
+        out.push('}');
+        out
+    }
+
+    async fn subtree(
+        db: &Database,
+        coll_name: &str,
+        id: &ObjectId,
+        fk_fields: &FkFieldMap,
+        seen: &mut HashSet<(String, ObjectId)>,
+        out: &mut Vec<serde_json::Value>,
+    ) -> Res<()> {
+        if !seen.insert((coll_name.to_owned(), *id)) {
+            return Ok(());
+        }
+        let Some(dok) = Tumongo::find_one_doc(db, coll_name, doc! {"_id": id}, None).await? else {
+            return Err(format!("No {id} in {coll_name}").into());
+        };
+        out.push(serde_json::json!({
+            "coll": coll_name,
+            "doc": Bson::Document(dok).into_canonical_extjson(),
+        }));
+        if let Some(children) = fk_fields.get(coll_name) {
+            for item in children {
+                let field = &item.field_name;
+                let doks = Tumongo::find_docs(db, &item.coll, doc! {field: id}, None).await?;
+                for child in doks {
+                    if let Ok(child_id) = child.get_object_id("_id") {
+                        Box::pin(Self::subtree(db, &item.coll, &child_id, fk_fields, seen, out)).await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// the doc and its fk children, recursively, as `[{"coll", "doc"}]` canonical extended json
+    pub async fn dump(db: &Database, coll_name: &str, id: &str) -> Res<String> {
+        let id = ObjectId::parse_str(id)?;
+        let mut out = vec![];
+        Self::subtree(db, coll_name, &id, Tumongo::fk_fields(), &mut HashSet::new(), &mut out).await?;
+        Ok(serde_json::to_string_pretty(&out)?)
+    }
+
+    async fn upsert(db: &Database, coll_name: &str, val: serde_json::Value) -> Res<()> {
+        let Bson::Document(dok) = Bson::try_from(val)? else {
+            return Err(format!("Expected a doc for {coll_name}").into());
+        };
+        match dok.get("_id").cloned() {
+            Some(_id) => {
+                Tumongo::coll::<Document>(db, coll_name)?
+                    .replace_one(doc! {"_id": _id}, dok)
+                    .upsert(true)
+                    .await?;
+            }
+            None => {
+                Tumongo::coll::<Document>(db, coll_name)?.insert_one(dok).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// upserts the docs of a [Cli::dump] by _id, returns how many
+    pub async fn restore(db: &Database, dump: &str) -> Res<usize> {
+        let entries: Vec<serde_json::Value> = serde_json::from_str(dump)?;
+        let n = entries.len();
+        for mut entry in entries {
+            let Some(coll_name) = entry.get("coll").and_then(|c| c.as_str()).map(|c| c.to_owned()) else {
+                return Err("Dump entry without a coll".into());
+            };
+            Self::upsert(db, &coll_name, entry["doc"].take()).await?;
+        }
+        Ok(n)
+    }
+
+    /// upserts `{"coll": [docs]}`, docs with an _id are replaced so reseeding is safe
+    pub async fn seed(db: &Database, seed: &str) -> Res<usize> {
+        let colls: serde_json::Map<String, serde_json::Value> = serde_json::from_str(seed)?;
+        let mut n = 0;
+        for (coll_name, doks) in colls {
+            let serde_json::Value::Array(doks) = doks else {
+                return Err(format!("Seed for {coll_name} should be an array").into());
+            };
+            for dok in doks {
+                Self::upsert(db, &coll_name, dok).await?;
+                n += 1;
+            }
+        }
+        Ok(n)
+    }
+}
@@ -0,0 +1,257 @@
+use mongodb::{Client, Database, options::ClientOptions};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::{collections::HashMap, env, path::Path, sync::Arc, time::Duration};
+
+use crate::{DB, FK_FIELDS, Res, Tumongo};
+
+/// name of the connection used when none is given
+pub const DEFAULT_CONN: &str = "default";
+
+static CONNECTIONS: OnceCell<Connections> = OnceCell::new();
+
+/// settings of one named connection
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ConnOpts {
+    pub url: String,
+    pub db_name: String,
+    pub app_name: Option<String>,
+    pub max_pool_size: Option<u32>,
+    pub min_pool_size: Option<u32>,
+    pub connect_timeout_ms: Option<u64>,
+    pub server_selection_timeout_ms: Option<u64>,
+}
+
+impl ConnOpts {
+    pub fn new(url: &str, db_name: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+            db_name: db_name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn app_name(mut self, app_name: &str) -> Self {
+        self.app_name = Some(app_name.to_owned());
+        self
+    }
+
+    pub fn pool_size(mut self, min: Option<u32>, max: Option<u32>) -> Self {
+        self.min_pool_size = min;
+        self.max_pool_size = max;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout_ms = Some(timeout.as_millis() as u64);
+        self
+    }
+
+    pub fn server_selection_timeout(mut self, timeout: Duration) -> Self {
+        self.server_selection_timeout_ms = Some(timeout.as_millis() as u64);
+        self
+    }
+
+    async fn client_options(&self) -> Res<ClientOptions> {
+        let mut opts = ClientOptions::parse(&self.url).await?;
+        if self.app_name.is_some() {
+            opts.app_name = self.app_name.clone();
+        }
+        if self.max_pool_size.is_some() {
+            opts.max_pool_size = self.max_pool_size;
+        }
+        if self.min_pool_size.is_some() {
+            opts.min_pool_size = self.min_pool_size;
+        }
+        if let Some(ms) = self.connect_timeout_ms {
+            opts.connect_timeout = Some(Duration::from_millis(ms));
+        }
+        if let Some(ms) = self.server_selection_timeout_ms {
+            opts.server_selection_timeout = Some(Duration::from_millis(ms));
+        }
+        Ok(opts)
+    }
+}
+
+/// named connections for [Tumongo::connect].
+///
+/// ```toml
+/// default = "main"
+///
+/// [connections.main]
+/// url = "mongodb://localhost:27017"
+/// db_name = "app"
+/// max_pool_size = 20
+///
+/// [connections.analytics]
+/// url = "mongodb://localhost:27018"
+/// db_name = "analytics"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TumongoConfig {
+    /// name of the default connection, [DEFAULT_CONN] if None
+    pub default: Option<String>,
+    #[serde(default)]
+    pub connections: HashMap<String, ConnOpts>,
+}
+
+fn env_var(k: &str) -> Option<String> {
+    env::var(k).ok().filter(|v| !v.is_empty())
+}
+
+fn env_parse<T: std::str::FromStr>(k: &str) -> Res<Option<T>> {
+    match env_var(k) {
+        Some(v) => match v.parse() {
+            Ok(v) => Ok(Some(v)),
+            Err(_) => Err(format!("Invalid value for {k}: {v}").into()),
+        },
+        None => Ok(None),
+    }
+}
+
+impl TumongoConfig {
+    pub fn builder() -> Self {
+        Self::default()
+    }
+
+    /// adds or replaces the connection `name`, the first one added becomes the default
+    pub fn connection(mut self, name: &str, opts: ConnOpts) -> Self {
+        if self.default.is_none() {
+            self.default = Some(name.to_owned());
+        }
+        self.connections.insert(name.to_owned(), opts);
+        self
+    }
+
+    pub fn default_conn(mut self, name: &str) -> Self {
+        self.default = Some(name.to_owned());
+        self
+    }
+
+    /// `MONGO_URL` and `MONGO_DB` for the default connection, plus `MONGO_APP_NAME`,
+    /// `MONGO_MAX_POOL_SIZE`, `MONGO_MIN_POOL_SIZE`, `MONGO_CONNECT_TIMEOUT_MS` and
+    /// `MONGO_SERVER_SELECTION_TIMEOUT_MS`.
+    ///
+    /// `TUMONGO_CONNECTIONS=analytics,logs` adds more connections, read from the same
+    /// vars suffixed with the upper case name, e.g `MONGO_URL_ANALYTICS`
+    pub fn from_env() -> Res<Self> {
+        let mut config = Self::builder().connection(DEFAULT_CONN, Self::conn_from_env("")?);
+        for name in env_var("TUMONGO_CONNECTIONS").unwrap_or_default().split(',') {
+            let name = name.trim();
+            if name.is_empty() {
+                continue;
+            }
+            let suffix = format!("_{}", name.to_uppercase());
+            config = config.connection(name, Self::conn_from_env(&suffix)?);
+        }
+        Ok(config)
+    }
+
+    fn conn_from_env(suffix: &str) -> Res<ConnOpts> {
+        let Some(url) = env_var(&format!("MONGO_URL{suffix}")) else {
+            return Err(format!("MONGO_URL{suffix} is not set").into());
+        };
+        let Some(db_name) = env_var(&format!("MONGO_DB{suffix}")) else {
+            return Err(format!("MONGO_DB{suffix} is not set").into());
+        };
+        Ok(ConnOpts {
+            url,
+            db_name,
+            app_name: env_var(&format!("MONGO_APP_NAME{suffix}")),
+            max_pool_size: env_parse(&format!("MONGO_MAX_POOL_SIZE{suffix}"))?,
+            min_pool_size: env_parse(&format!("MONGO_MIN_POOL_SIZE{suffix}"))?,
+            connect_timeout_ms: env_parse(&format!("MONGO_CONNECT_TIMEOUT_MS{suffix}"))?,
+            server_selection_timeout_ms: env_parse(&format!(
+                "MONGO_SERVER_SELECTION_TIMEOUT_MS{suffix}"
+            ))?,
+        })
+    }
+
+    pub fn from_toml_str(s: &str) -> Res<Self> {
+        Ok(toml::from_str(s)?)
+    }
+
+    pub fn from_toml_file(path: impl AsRef<Path>) -> Res<Self> {
+        Self::from_toml_str(&std::fs::read_to_string(path)?)
+    }
+
+    fn default_name(&self) -> &str {
+        self.default.as_deref().unwrap_or(DEFAULT_CONN)
+    }
+}
+
+/// the databases of a [TumongoConfig], cheap to clone
+#[derive(Debug, Clone)]
+pub struct Connections {
+    dbs: Arc<HashMap<String, Database>>,
+    default: String,
+}
+
+impl Connections {
+    /// the default connection's database
+    pub fn db(&self) -> &Database {
+        &self.dbs[&self.default]
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Database> {
+        self.dbs.get(name)
+    }
+
+    pub fn database(&self, name: &str) -> Res<&Database> {
+        self.get(name)
+            .ok_or_else(|| format!("No connection named {name}").into())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.dbs.keys().map(|k| k.as_str())
+    }
+}
+
+impl Tumongo {
+    /// opens every connection of `config`.
+    ///
+    /// the first call also makes them the global default behind [Tumongo::conns] and `dbase()`.
+    ///
+    /// errors if `tumongo::register!()` wasn't called first, it has to run in the crate
+    /// defining the models
+    pub async fn connect(config: &TumongoConfig) -> Res<Connections> {
+        if FK_FIELDS.get().is_none() {
+            return Err("Models are not registered, call tumongo::register!() before connecting".into());
+        }
+        let default = config.default_name().to_owned();
+        if !config.connections.contains_key(&default) {
+            return Err(format!("Default connection {default} is not configured").into());
+        }
+
+        let mut clients: HashMap<String, Client> = HashMap::new();
+        let mut dbs = HashMap::new();
+        for (name, opts) in &config.connections {
+            println!("\n[{name}] Connecting to [{}]...", opts.db_name);
+            // connections to the same server share a client and its pool
+            let key = format!("{:?}", ConnOpts { db_name: String::new(), ..opts.clone() });
+            let client = match clients.get(&key) {
+                Some(client) => client.clone(),
+                None => {
+                    let client = Client::with_options(opts.client_options().await?)?;
+                    clients.insert(key, client.clone());
+                    client
+                }
+            };
+            dbs.insert(name.clone(), client.database(&opts.db_name));
+        }
+
+        let conns = Connections {
+            dbs: Arc::new(dbs),
+            default,
+        };
+        if CONNECTIONS.set(conns.clone()).is_ok() {
+            DB.set(conns.db().clone()).ok();
+        }
+        Ok(conns)
+    }
+
+    /// the connections of the first [Tumongo::connect]
+    pub fn conns() -> Option<&'static Connections> {
+        CONNECTIONS.get()
+    }
+}
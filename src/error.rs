@@ -0,0 +1,45 @@
+use mongodb::bson::{Document, oid::ObjectId};
+use std::fmt;
+
+/// returned by `save` when a versioned doc was changed by someone else since it was loaded
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub coll: String,
+    pub id: ObjectId,
+    /// the version the instance was expected to be at
+    pub version: i64,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "VERSION CONFLICT: [{}] {} is no longer at version {}",
+            self.coll, self.id, self.version
+        )
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+/// a stored doc that couldn't be turned into its model
+#[derive(Debug, Clone)]
+pub struct DeserializeFailure {
+    pub coll: String,
+    pub id: Option<ObjectId>,
+    pub error: String,
+    /// the doc as stored
+    pub dok: Document,
+}
+
+impl fmt::Display for DeserializeFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "DESERIALIZE ERROR: [{}] {:?}: {}",
+            self.coll, self.id, self.error
+        )
+    }
+}
+
+impl std::error::Error for DeserializeFailure {}
@@ -0,0 +1,411 @@
+use futures_util::{StreamExt, TryStreamExt, stream::BoxStream};
+use mongodb::{
+    ClientSession, Cursor, Database,
+    bson::{self, Bson, Document, doc, oid::ObjectId},
+    options::{FindOptions, ReadConcern, SelectionCriteria},
+};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+
+use crate::{DeserializeFailure, Model, Projection, Res, Tumongo};
+
+/// field set by soft deletes
+pub const DELETED_AT: &str = "deleted_at";
+
+/// which docs of a soft delete model a query sees
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Deleted {
+    /// only live docs
+    #[default]
+    Exclude,
+    /// live and soft deleted docs
+    Include,
+    /// only soft deleted docs
+    Only,
+}
+
+impl Deleted {
+    /// adds the `deleted_at` condition unless the filter already has one
+    pub fn apply(&self, mut filter: Document) -> Document {
+        if filter.contains_key(DELETED_AT) {
+            return filter;
+        }
+        match self {
+            Deleted::Exclude => {
+                filter.insert(DELETED_AT, Bson::Null);
+            }
+            Deleted::Include => {}
+            Deleted::Only => {
+                filter.insert(DELETED_AT, doc! { "$ne": Bson::Null });
+            }
+        }
+        filter
+    }
+}
+
+/// what `find`/`find_one` do with stored docs that don't deserialize
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeserializeMode {
+    /// fail the whole call with a [DeserializeFailure]
+    Strict,
+    /// leave them out, their ids end up in [Found::skipped]
+    #[default]
+    Skip,
+    /// leave them out, the failures end up in [Found::failures]
+    Collect,
+}
+
+/// result of [Tumongo::find_all] and [Tumongo::find_all_as]
+#[derive(Debug)]
+pub struct Found<M> {
+    pub items: Vec<M>,
+    /// ids of the docs that failed to deserialize
+    pub skipped: Vec<ObjectId>,
+    /// only filled in [DeserializeMode::Collect]
+    pub failures: Vec<DeserializeFailure>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FindOpts {
+    pub skip: Option<u64>,
+    pub limit: Option<i64>,
+    pub sort: Option<Document>,
+    /// ignored for models without soft delete
+    pub deleted: Deleted,
+    /// docs per server round trip, the driver's default if None
+    pub batch_size: Option<u32>,
+    pub mode: DeserializeMode,
+    /// `$project` doc, prefer `find_as` with a Projection
+    pub projection: Option<Document>,
+    /// overrides the model's read_preference for this call
+    pub selection_criteria: Option<SelectionCriteria>,
+    /// overrides the model's read_concern for this call
+    pub read_concern: Option<ReadConcern>,
+}
+
+impl FindOpts {
+    /// default opts in [DeserializeMode::Strict]
+    pub fn strict() -> Self {
+        Self {
+            mode: DeserializeMode::Strict,
+            ..Default::default()
+        }
+    }
+
+    fn options(&self) -> FindOptions {
+        FindOptions::builder()
+            .skip(self.skip)
+            .limit(self.limit)
+            .sort(self.sort.clone())
+            .batch_size(self.batch_size)
+            .projection(self.projection.clone())
+            .selection_criteria(self.selection_criteria.clone())
+            .read_concern(self.read_concern.clone())
+            .build()
+    }
+}
+
+impl Tumongo {
+    /// applies the soft delete scope if the model has one
+    pub fn scope_filter<M: Model>(filter: Document, deleted: Deleted) -> Document {
+        if M::soft_delete() {
+            deleted.apply(filter)
+        } else {
+            filter
+        }
+    }
+
+    async fn cursor<M: Model>(
+        _db: &Database,
+        filter: Document,
+        opts: FindOpts,
+    ) -> Res<Cursor<Document>> {
+        let cursor = Self::coll::<Document>(_db, &M::coll_name())?
+            .find(Self::scope_filter::<M>(filter, opts.deleted))
+            .with_options(opts.options())
+            .await?;
+        Ok(cursor)
+    }
+
+    /// runs the find (in `sess` if given) and feeds the docs to `each` until it returns false
+    async fn each_doc<M: Model>(
+        _db: &Database,
+        filter: Document,
+        opts: FindOpts,
+        sess: Option<&mut ClientSession>,
+        mut each: impl FnMut(Document) -> Res<bool> + Send,
+    ) -> Res<()> {
+        Self::check_tenant::<M>(&filter)?;
+        let Some(sess) = sess else {
+            let mut cursor = Self::cursor::<M>(_db, filter, opts).await?;
+            while let Some(dok) = cursor.try_next().await? {
+                if !each(dok)? {
+                    break;
+                }
+            }
+            return Ok(());
+        };
+        let mut cursor = Self::coll::<Document>(_db, &M::coll_name())?
+            .find(Self::scope_filter::<M>(filter, opts.deleted))
+            .with_options(opts.options())
+            .session(&mut *sess)
+            .await?;
+        while let Some(dok) = cursor.next(&mut *sess).await.transpose()? {
+            if !each(dok)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// lazily yields the matching docs, docs that fail to deserialize come out as errors
+    pub async fn stream<M: Model + 'static>(
+        _db: &Database,
+        filter: Document,
+        opts: FindOpts,
+    ) -> Res<BoxStream<'static, Res<M>>> {
+        Self::check_tenant::<M>(&filter)?;
+        let cursor = Self::cursor::<M>(_db, filter, opts).await?;
+        Ok(cursor
+            .map(|dok| -> Res<M> { Ok(M::from_doc(dok?)?) })
+            .boxed())
+    }
+
+    /// matching docs plus what happened to the ones that didn't deserialize, see [DeserializeMode]
+    pub async fn find_all<M: Model>(
+        _db: &Database,
+        filter: Document,
+        opts: FindOpts,
+    ) -> Res<Found<M>> {
+        Self::find_all_in(_db, filter, opts, None).await
+    }
+
+    pub(crate) async fn find_all_in<M: Model>(
+        _db: &Database,
+        filter: Document,
+        opts: FindOpts,
+        sess: Option<&mut ClientSession>,
+    ) -> Res<Found<M>> {
+        let mode = opts.mode;
+        let mut found = Found {
+            items: vec![],
+            skipped: vec![],
+            failures: vec![],
+        };
+
+        Self::each_doc::<M>(_db, filter, opts, sess, |dok| {
+            match M::from_doc(dok.clone()) {
+                Ok(dok) => {
+                    found.items.push(dok);
+                }
+                Err(err) => {
+                    let failure = DeserializeFailure {
+                        coll: M::coll_name(),
+                        id: dok.get_object_id("_id").ok(),
+                        error: err.to_string(),
+                        dok,
+                    };
+                    match mode {
+                        DeserializeMode::Strict => return Err(failure.into()),
+                        DeserializeMode::Skip => {
+                            eprintln!("[find] [{}] Error serializing dok {:?}", failure.coll, failure.dok);
+                            eprintln!("{err:?}");
+                        }
+                        DeserializeMode::Collect => {}
+                    }
+                    found.skipped.extend(failure.id);
+                    if mode == DeserializeMode::Collect {
+                        found.failures.push(failure);
+                    }
+                }
+            }
+            Ok(true)
+        })
+        .await?;
+
+        Ok(found)
+    }
+
+    /// like [Tumongo::find] but only fetches and deserializes the fields of `P`
+    pub async fn find_as<M: Model, P: Projection<M>>(
+        _db: &Database,
+        filter: Document,
+        opts: FindOpts,
+    ) -> Res<Vec<P>> {
+        Ok(Self::find_all_as::<M, P>(_db, filter, opts).await?.items)
+    }
+
+    /// [Tumongo::find_all] for a projection `P`
+    pub async fn find_all_as<M: Model, P: Projection<M>>(
+        _db: &Database,
+        filter: Document,
+        mut opts: FindOpts,
+    ) -> Res<Found<P>> {
+        let mode = opts.mode;
+        opts.projection = Some(P::projection());
+        let mut found = Found {
+            items: vec![],
+            skipped: vec![],
+            failures: vec![],
+        };
+        Self::each_doc::<M>(_db, filter, opts, None, |dok| {
+            match P::from_doc(dok.clone()) {
+                Ok(item) => found.items.push(item),
+                Err(err) => {
+                    let failure = DeserializeFailure {
+                        coll: M::coll_name(),
+                        id: dok.get_object_id("_id").ok(),
+                        error: err.to_string(),
+                        dok,
+                    };
+                    match mode {
+                        DeserializeMode::Strict => return Err(failure.into()),
+                        DeserializeMode::Skip => eprintln!("[find_as] {failure}"),
+                        DeserializeMode::Collect => {}
+                    }
+                    found.skipped.extend(failure.id);
+                    if mode == DeserializeMode::Collect {
+                        found.failures.push(failure);
+                    }
+                }
+            }
+            Ok(true)
+        })
+        .await?;
+        Ok(found)
+    }
+
+    pub async fn find<M: Model>(_db: &Database, filter: Document, opts: FindOpts) -> Res<Vec<M>> {
+        Ok(Self::find_all(_db, filter, opts).await?.items)
+    }
+
+    /// [Tumongo::find] inside `sess`, e.g in a transaction
+    pub async fn find_with_session<M: Model>(
+        _db: &Database,
+        filter: Document,
+        opts: FindOpts,
+        sess: &mut ClientSession,
+    ) -> Res<Vec<M>> {
+        Ok(Self::find_all_in(_db, filter, opts, Some(sess)).await?.items)
+    }
+
+    /// first doc that deserializes, honours sort/skip.
+    ///
+    /// Strict and Collect fail on a bad doc, Skip moves on to the next match and
+    /// fails with the first bad doc if none deserialized. the generated `find_one`
+    /// and `find_by_id` use [FindOpts::strict]
+    pub async fn find_one<M: Model>(_db: &Database, filter: Document, opts: FindOpts) -> Res<M> {
+        Self::find_one_in(_db, filter, opts, None).await
+    }
+
+    /// [Tumongo::find_one] inside `sess`
+    pub async fn find_one_with_session<M: Model>(
+        _db: &Database,
+        filter: Document,
+        opts: FindOpts,
+        sess: &mut ClientSession,
+    ) -> Res<M> {
+        Self::find_one_in(_db, filter, opts, Some(sess)).await
+    }
+
+    pub(crate) async fn find_one_in<M: Model>(
+        _db: &Database,
+        filter: Document,
+        mut opts: FindOpts,
+        sess: Option<&mut ClientSession>,
+    ) -> Res<M> {
+        let mode = opts.mode;
+        if mode != DeserializeMode::Skip && opts.limit.is_none() {
+            opts.limit = Some(1);
+        }
+        let mut item = None;
+        let mut first_failure = None;
+        Self::each_doc::<M>(_db, filter.clone(), opts, sess, |dok| {
+            match M::from_doc(dok.clone()) {
+                Ok(found) => {
+                    item = Some(found);
+                    return Ok(false);
+                }
+                Err(err) => {
+                    let failure = DeserializeFailure {
+                        coll: M::coll_name(),
+                        id: dok.get_object_id("_id").ok(),
+                        error: err.to_string(),
+                        dok,
+                    };
+                    if mode != DeserializeMode::Skip {
+                        return Err(failure.into());
+                    }
+                    eprintln!("[find_one] {failure}");
+                    first_failure.get_or_insert(failure);
+                }
+            }
+            Ok(true)
+        })
+        .await?;
+        match (item, first_failure) {
+            (Some(item), _) => Ok(item),
+            (None, Some(failure)) => Err(failure.into()),
+            (None, None) => Err(format!("No matching item in {} for {filter:?}", M::coll_name()).into()),
+        }
+    }
+}
+
+impl Tumongo {
+    pub async fn count<M: Model>(_db: &Database, filter: Document) -> Res<u64> {
+        Self::check_tenant::<M>(&filter)?;
+        let n = Self::coll::<Document>(_db, &M::coll_name())?
+            .count_documents(Self::scope_filter::<M>(filter, Deleted::Exclude))
+            .await?;
+        Ok(n)
+    }
+
+    /// from collection metadata, fast but counts soft deleted docs too.
+    /// fails for tenant models, it would count every tenant's docs
+    pub async fn estimated_count<M: Model>(_db: &Database) -> Res<u64> {
+        if let Some(field) = M::tenant_field() {
+            return Err(format!("[{}] estimated_count ignores {field}, use TenantScope::count", M::coll_name()).into());
+        }
+        let n = Self::coll::<Document>(_db, &M::coll_name())?
+            .estimated_document_count()
+            .await?;
+        Ok(n)
+    }
+
+    pub async fn exists<M: Model>(_db: &Database, filter: Document) -> Res<bool> {
+        Self::check_tenant::<M>(&filter)?;
+        let dok = Self::coll::<Document>(_db, &M::coll_name())?
+            .find_one(Self::scope_filter::<M>(filter, Deleted::Exclude))
+            .projection(doc! {"_id": 1})
+            .await?;
+        Ok(dok.is_some())
+    }
+
+    /// distinct values of `field` among the matching docs
+    pub async fn distinct<M: Model, V: DeserializeOwned>(
+        _db: &Database,
+        field: &str,
+        filter: Document,
+    ) -> Res<Vec<V>> {
+        Self::check_tenant::<M>(&filter)?;
+        let field = if field == "id" { "_id" } else { field };
+        let vals = Self::coll::<Document>(_db, &M::coll_name())?
+            .distinct(field, Self::scope_filter::<M>(filter, Deleted::Exclude))
+            .await?;
+        let mut res = Vec::with_capacity(vals.len());
+        for v in vals {
+            res.push(bson::from_bson(v)?);
+        }
+        Ok(res)
+    }
+
+    /// docs for `ids` in the same order, ids without a doc are left out
+    pub async fn find_by_ids<M: Model>(_db: &Database, ids: &[ObjectId]) -> Res<Vec<M>> {
+        let found: Vec<M> = Self::find(_db, doc! {"_id": {"$in": ids.to_vec()}}, FindOpts::default()).await?;
+        let mut by_id: HashMap<ObjectId, M> = found
+            .into_iter()
+            .filter_map(|item| item.id().map(|_id| (_id, item)))
+            .collect();
+        Ok(ids.iter().filter_map(|_id| by_id.remove(_id)).collect())
+    }
+}
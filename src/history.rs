@@ -0,0 +1,212 @@
+use futures_util::TryStreamExt;
+use mongodb::{
+    ClientSession, Database,
+    bson::{self, Bson, Document, doc, oid::ObjectId},
+};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+use crate::{Res, Tumongo, db};
+
+static HISTORY_ACTOR: OnceCell<fn() -> Option<String>> = OnceCell::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryOp {
+    Insert,
+    Update,
+    Delete,
+    Restore,
+    Revert,
+}
+
+/// one entry of a `<coll>_history` collection, written for #[tumongo(history)] models
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryRecord {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// coll_name of the model
+    pub model: String,
+    pub doc_id: ObjectId,
+    /// 1 for the first record of a doc, see [Tumongo::revert_to]
+    pub version: i64,
+    pub op: HistoryOp,
+    /// old values of the changed fields, missing = field didn't exist
+    pub before: Document,
+    /// new values of the changed fields, missing = field was removed
+    pub after: Document,
+    pub actor: Option<String>,
+    pub at: bson::DateTime,
+}
+
+impl Tumongo {
+    pub fn history_coll(coll_name: &str) -> String {
+        format!("{coll_name}_history")
+    }
+
+    /// sets where history records get their actor from, e.g a task local holding the current user
+    pub fn set_history_actor(f: fn() -> Option<String>) {
+        if HISTORY_ACTOR.set(f).is_err() {
+            eprintln!("[history] actor fn already set");
+        }
+    }
+
+    /// writes the diff between `before` and `after` into `<coll>_history`, nothing if they're equal
+    pub async fn record_history(
+        db: &Database,
+        sess: &mut ClientSession,
+        coll_name: &str,
+        doc_id: &ObjectId,
+        op: HistoryOp,
+        before: &Document,
+        after: &Document,
+    ) -> db::error::Result<()> {
+        let (set, unset) = Self::diff(Some(before), after);
+        if set.is_empty() && unset.is_empty() {
+            return Ok(());
+        }
+        let mut before_vals = Document::new();
+        let mut after_vals = Document::new();
+        for k in set.keys().chain(unset.keys()) {
+            if let Some(v) = before.get(k) {
+                before_vals.insert(k.to_owned(), v.clone());
+            }
+            if let Some(v) = after.get(k) {
+                after_vals.insert(k.to_owned(), v.clone());
+            }
+        }
+
+        // history lives next to the model's collection, so does its version counter
+        let history_db = Self::route_db(db, coll_name)?;
+        let history_coll = Self::history_coll(coll_name);
+        let coll = history_db.collection::<HistoryRecord>(&history_coll);
+        // a counter per doc, concurrent saves can't end up with the same version
+        let version = Self::next_sequence(&history_db, sess, &history_coll, Some((*doc_id).into())).await?;
+        let record = HistoryRecord {
+            id: None,
+            model: coll_name.to_owned(),
+            doc_id: *doc_id,
+            version,
+            op,
+            before: before_vals,
+            after: after_vals,
+            actor: HISTORY_ACTOR.get().and_then(|f| f()),
+            at: bson::DateTime::now(),
+        };
+        coll.insert_one(record).session(&mut *sess).await?;
+        Ok(())
+    }
+
+    /// records of a doc, oldest first. fails for tenant collections, see [crate::TenantScope::history]
+    pub async fn history(db: &Database, coll_name: &str, id: &ObjectId) -> Res<Vec<HistoryRecord>> {
+        Self::refuse_tenant_history(coll_name)?;
+        Self::history_records(db, coll_name, id).await
+    }
+
+    /// puts the doc back to how it was right after history record `version`,
+    /// also works for hard deleted docs. returns the reverted doc.
+    /// fails for tenant collections, see [crate::TenantScope::revert_to]
+    pub async fn revert_to(
+        db: &Database,
+        coll_name: &str,
+        id: &ObjectId,
+        version: i64,
+    ) -> Res<Document> {
+        Self::refuse_tenant_history(coll_name)?;
+        let records = Self::history_records(db, coll_name, id).await?;
+        Self::revert_records(db, coll_name, id, version, records, None).await
+    }
+
+    fn refuse_tenant_history(coll_name: &str) -> Res<()> {
+        match Self::tenant_field(coll_name) {
+            Some(field) => Err(format!("[{coll_name}] history is scoped by {field}, use a TenantScope").into()),
+            None => Ok(()),
+        }
+    }
+
+    async fn history_records(db: &Database, coll_name: &str, id: &ObjectId) -> Res<Vec<HistoryRecord>> {
+        let records = Self::route_db(db, coll_name)?
+            .collection::<HistoryRecord>(&Self::history_coll(coll_name))
+            .find(doc! {"doc_id": id})
+            .sort(doc! {"version": 1})
+            .await?
+            .try_collect()
+            .await?;
+        Ok(records)
+    }
+
+    /// history of a doc of `tenant`, the stored doc or the latest record tells whose it is
+    pub(crate) async fn tenant_history(
+        db: &Database,
+        coll_name: &str,
+        id: &ObjectId,
+        field: &str,
+        tenant: &Bson,
+    ) -> Res<Vec<HistoryRecord>> {
+        let records = Self::history_records(db, coll_name, id).await?;
+        if records.is_empty() {
+            return Ok(records);
+        }
+        let owner = match Self::find_one_doc(db, coll_name, doc! {"_id": id}, None).await? {
+            Some(dok) => dok.get(field).cloned(),
+            // hard deleted, the delete record has the whole doc in before
+            None => records
+                .iter()
+                .rev()
+                .find_map(|r| r.after.get(field).or(r.before.get(field)).cloned()),
+        };
+        if owner.as_ref() != Some(tenant) {
+            return Err(format!("[{coll_name}] {id} belongs to another tenant").into());
+        }
+        Ok(records)
+    }
+
+    /// `tenant` is the (field, value) the reverted doc must keep
+    pub(crate) async fn revert_records(
+        db: &Database,
+        coll_name: &str,
+        id: &ObjectId,
+        version: i64,
+        records: Vec<HistoryRecord>,
+        tenant: Option<(&str, &Bson)>,
+    ) -> Res<Document> {
+        if !records.iter().any(|r| r.version == version) {
+            return Err(format!("No history version {version} for {id} in {coll_name}").into());
+        }
+        let coll = Self::coll::<Document>(db, coll_name)?;
+        let mut sess = Self::route_db(db, coll_name)?.client().start_session().await?;
+        let current = coll
+            .find_one(doc! {"_id": id})
+            .session(&mut sess)
+            .await?
+            .unwrap_or_default();
+
+        let mut dok = current.clone();
+        for record in records.iter().rev().take_while(|r| r.version > version) {
+            for k in record.before.keys().chain(record.after.keys()) {
+                match record.before.get(k) {
+                    Some(v) => {
+                        dok.insert(k.to_owned(), v.clone());
+                    }
+                    None => {
+                        dok.remove(k);
+                    }
+                }
+            }
+        }
+        dok.insert("_id", *id);
+        if let Some((field, tenant)) = tenant
+            && dok.get(field) != Some(tenant)
+        {
+            return Err(format!("[{coll_name}] version {version} of {id} belongs to another tenant").into());
+        }
+
+        coll.replace_one(doc! {"_id": id}, &dok)
+            .upsert(true)
+            .session(&mut sess)
+            .await?;
+        Self::record_history(db, &mut sess, coll_name, id, HistoryOp::Revert, &current, &dok)
+            .await?;
+        Ok(dok)
+    }
+}
@@ -0,0 +1,216 @@
+use futures_util::TryStreamExt;
+use mongodb::{
+    Database, IndexModel,
+    bson::{self, Bson, Document},
+    error::ErrorKind,
+    options::IndexOptions,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::{Model, Res, Tumongo};
+
+/// an index declared with `#[tumongo(index(..))]` or `#[tumongo(ttl = "..")]`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct IndexSpec {
+    /// mongo's default name (e.g `company_id_1_created_at_-1`) if None
+    pub name: Option<String>,
+    /// (path, kind), kind is 1, -1, text or 2dsphere
+    pub keys: Vec<(String, String)>,
+    #[serde(default)]
+    pub unique: bool,
+    #[serde(default)]
+    pub sparse: bool,
+    pub ttl_secs: Option<u64>,
+    /// partialFilterExpression as json
+    pub partial: Option<String>,
+}
+
+impl IndexSpec {
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            self.keys
+                .iter()
+                .map(|(path, kind)| format!("{path}_{kind}"))
+                .collect::<Vec<_>>()
+                .join("_")
+        })
+    }
+
+    pub fn keys_doc(&self) -> Document {
+        let mut keys = Document::new();
+        for (path, kind) in &self.keys {
+            let val = match kind.parse::<i32>() {
+                Ok(dir) => Bson::Int32(dir),
+                Err(_) => Bson::String(kind.clone()),
+            };
+            keys.insert(path, val);
+        }
+        keys
+    }
+
+    fn is_text(&self) -> bool {
+        self.keys.iter().any(|(_, kind)| kind == "text")
+    }
+
+    fn partial_doc(&self) -> Res<Option<Document>> {
+        let Some(partial) = &self.partial else {
+            return Ok(None);
+        };
+        let val: serde_json::Value = serde_json::from_str(partial)?;
+        Ok(Some(bson::to_document(&val)?))
+    }
+
+    pub fn model(&self) -> Res<IndexModel> {
+        let opts = IndexOptions::builder()
+            .name(self.name())
+            .unique(self.unique.then_some(true))
+            .sparse(self.sparse.then_some(true))
+            .expire_after(self.ttl_secs.map(Duration::from_secs))
+            .partial_filter_expression(self.partial_doc()?)
+            .build();
+        Ok(IndexModel::builder()
+            .keys(self.keys_doc())
+            .options(opts)
+            .build())
+    }
+
+    /// true if `existing` (from list_indexes) is this index
+    fn matches(&self, existing: &IndexModel) -> Res<bool> {
+        let opts = existing.options.clone().unwrap_or_default();
+        // text indexes are stored as {_fts: "text", _ftsx: 1}, the name has to do
+        if !self.is_text() && !same_vals(&Bson::Document(existing.keys.clone()), &Bson::Document(self.keys_doc())) {
+            return Ok(false);
+        }
+        let partial = match (self.partial_doc()?, opts.partial_filter_expression) {
+            (Some(a), Some(b)) => same_vals(&Bson::Document(a), &Bson::Document(b)),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        Ok(partial
+            && opts.unique.unwrap_or(false) == self.unique
+            && opts.sparse.unwrap_or(false) == self.sparse
+            && opts.expire_after.map(|d| d.as_secs()) == self.ttl_secs)
+    }
+}
+
+/// compares as relaxed extended json, the server may hand numbers back as another int type
+fn same_vals(a: &Bson, b: &Bson) -> bool {
+    a.clone().into_relaxed_extjson() == b.clone().into_relaxed_extjson()
+}
+
+/// what [Tumongo::sync_indexes] did, by index name
+#[derive(Debug, Clone, Default)]
+pub struct IndexSync {
+    pub created: Vec<String>,
+    /// declared but different from the existing one, dropped and created again
+    pub changed: Vec<String>,
+    /// existing but not declared, only dropped with `drop_undeclared`
+    pub dropped: Vec<String>,
+    pub undeclared: Vec<String>,
+    pub unchanged: Vec<String>,
+}
+
+impl IndexSync {
+    pub fn is_noop(&self) -> bool {
+        self.created.is_empty() && self.changed.is_empty() && self.dropped.is_empty()
+    }
+}
+
+impl Tumongo {
+    /// makes the indexes of `M` match its declared ones.
+    ///
+    /// missing ones are created, changed ones recreated, undeclared ones are only
+    /// dropped with `drop_undeclared`. `_id_` is never touched
+    pub async fn sync_indexes<M: Model>(db: &Database, drop_undeclared: bool) -> Res<IndexSync> {
+        let coll = Self::coll::<Document>(db, &M::coll_name())?;
+        let declared = M::indexes();
+        let existing: Vec<IndexModel> = match coll.list_indexes().await {
+            Ok(cursor) => cursor.try_collect().await?,
+            // NamespaceNotFound, the collection doesn't exist yet
+            Err(err) if matches!(&*err.kind, ErrorKind::Command(e) if e.code == 26) => vec![],
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut res = IndexSync::default();
+        for idx in &existing {
+            let name = idx
+                .options
+                .as_ref()
+                .and_then(|o| o.name.clone())
+                .unwrap_or_default();
+            if name == "_id_" {
+                continue;
+            }
+            match declared.iter().find(|spec| spec.name() == name) {
+                Some(spec) if spec.matches(idx)? => res.unchanged.push(name),
+                Some(_) => {
+                    println!("\n[{}] Dropping changed index {name}...", M::coll_name());
+                    coll.drop_index(&name).await?;
+                    res.changed.push(name);
+                }
+                None => {
+                    if drop_undeclared {
+                        println!("\n[{}] Dropping undeclared index {name}...", M::coll_name());
+                        coll.drop_index(&name).await?;
+                        res.dropped.push(name);
+                    } else {
+                        res.undeclared.push(name);
+                    }
+                }
+            }
+        }
+
+        for spec in &declared {
+            let name = spec.name();
+            if res.unchanged.contains(&name) {
+                continue;
+            }
+            println!("\n[{}] Creating index {name}...", M::coll_name());
+            coll.create_index(spec.model()?).await?;
+            if !res.changed.contains(&name) {
+                res.created.push(name);
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+
+    use super::*;
+
+    fn spec(keys: &[(&str, &str)]) -> IndexSpec {
+        IndexSpec {
+            name: None,
+            keys: keys.iter().map(|(p, k)| (p.to_string(), k.to_string())).collect(),
+            unique: false,
+            sparse: false,
+            ttl_secs: None,
+            partial: None,
+        }
+    }
+
+    #[test]
+    fn default_name_is_mongos() {
+        assert_eq!(spec(&[("company_id", "1"), ("created_at", "-1")]).name(), "company_id_1_created_at_-1");
+        assert_eq!(spec(&[("bio", "text")]).name(), "bio_text");
+    }
+
+    #[test]
+    fn given_name_wins() {
+        let spec = IndexSpec {
+            name: Some("by_company".into()),
+            ..spec(&[("company_id", "1")])
+        };
+        assert_eq!(spec.name(), "by_company");
+    }
+
+    #[test]
+    fn keys_doc_keeps_order_and_kinds() {
+        let keys = spec(&[("company_id", "1"), ("created_at", "-1"), ("loc", "2dsphere")]).keys_doc();
+        assert_eq!(keys, doc! {"company_id": 1, "created_at": -1, "loc": "2dsphere"});
+        assert_eq!(keys.keys().collect::<Vec<_>>(), ["company_id", "created_at", "loc"]);
+    }
+}
@@ -1,20 +1,62 @@
 pub mod a;
+#[cfg(feature = "cli")]
+pub mod cli;
+mod aggregate;
+mod bulk;
+mod connection;
+mod error;
+mod find;
+mod history;
+mod index;
+mod migration;
+mod paginate;
+mod query;
+mod route;
+mod sequence;
+mod snapshot;
+mod soft_delete;
+mod tenant;
 mod traits;
+mod transaction;
+mod update;
+mod upsert;
+mod validation;
+pub use async_trait;
 pub use futures_util;
 use futures_util::{TryStreamExt, lock::Mutex};
 pub use mongodb as db;
 use mongodb::{
-    Client, ClientSession, Database,
+    ClientSession, Database,
     bson::{self, Document, doc, oid::ObjectId},
 };
 pub use once_cell;
 use once_cell::sync::OnceCell;
-use serde::de::Visitor;
+pub use serde;
 pub use serde::{Deserialize, Serialize};
+pub use aggregate::{Aggregate, TypedAggregate};
+pub use bulk::{BulkItemResult, BulkResult, BulkWrite};
+pub use connection::{ConnOpts, Connections, DEFAULT_CONN, TumongoConfig};
+pub use error::*;
+pub use find::*;
+pub use history::{HistoryOp, HistoryRecord};
+pub use index::{IndexSpec, IndexSync};
+pub use migration::{MIGRATIONS_COLL, Migration, MigrationRecord};
+pub use paginate::{CursorPage, Page};
+pub use query::{Field, Filter, Query, Sort};
+pub use sequence::COUNTERS_COLL;
 pub use serde_json;
+pub use snapshot::Snapshot;
+pub use soft_delete::DeletedScope;
+pub use tenant::TenantScope;
+pub use traits::*;
+pub use transaction::{Tx, TxOpts};
+pub use update::Update;
+pub use validation::ValidatorOpts;
 use std::{collections::HashMap, env, ops::Deref, str::FromStr, sync::Arc};
 use strum_macros::{EnumString, VariantNames};
 pub use tumongo_macros::*;
+// lets `register!` use `::tumongo::` paths inside this crate too
+extern crate self as tumongo;
 #[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, VariantNames, Serialize, Deserialize)]
 #[strum(serialize_all = "snake_case")]
 pub enum OnDelete {
@@ -31,11 +73,40 @@ pub struct FkField {
 
 pub type FkFieldMap = HashMap<String, Vec<FkField>>;
 
+/// where a model's collection lives and how it's accessed, see [Tumongo::route_db] and [Tumongo::coll]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CollRoute {
+    /// connection name in [TumongoConfig]
+    pub conn: Option<String>,
+    /// database name
+    pub db: Option<String>,
+    /// primary, primary_preferred, secondary, secondary_preferred or nearest
+    pub read_preference: Option<String>,
+    /// local, available, majority, linearizable or snapshot
+    pub read_concern: Option<String>,
+    /// majority, a node count or a tag set name
+    pub write_concern: Option<String>,
+    /// field holding the tenant, see [TenantScope]
+    pub tenant_field: Option<String>,
+}
+
 pub static FK_FIELDS: OnceCell<FkFieldMap> = OnceCell::new();
 pub static REF_FIELDS: OnceCell<FkFieldMap> = OnceCell::new();
 pub static UNIQUE_FIELDS: OnceCell<HashMap<String, Vec<String>>> = OnceCell::new();
+/// collections of #[tumongo(soft_delete)] models
+pub static SOFT_DELETE_COLLS: OnceCell<Vec<String>> = OnceCell::new();
+/// key = coll_name, only models with a conn, db, read/write settings or tenant field
+pub static COLL_ROUTES: OnceCell<HashMap<String, CollRoute>> = OnceCell::new();
 pub static DB: OnceCell<Database> = OnceCell::new();
 type SyncDoc = Arc<Mutex<Document>>;
+
+/// what stays the same through the recursive populate calls
+struct PopulateCtx<'a> {
+    db: &'a Database,
+    fk_fields: &'a FkFieldMap,
+    ref_fields: &'a FkFieldMap,
+}
+
 pub type Res<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
 pub struct Tumongo;
 
@@ -49,8 +120,15 @@ impl Tumongo {
         is_child: bool,
     ) -> db::error::Result<()> {
         if let Some(child_colls) = fk_fields.get(coll_name) {
+            // children of other tenants are never touched
+            let tenant = Self::stored_tenant(db, coll_name, id, Some(&mut *sess)).await?;
             for item in child_colls {
                 let field_name = &item.field_name;
+                let mut own_sess = Self::child_session(db, coll_name, &item.coll).await?;
+                let sess = match own_sess.as_mut() {
+                    Some(own_sess) => own_sess,
+                    None => &mut *sess,
+                };
 
                 let on_delete = item
                     .on_delete
@@ -63,10 +141,10 @@ impl Tumongo {
                         OnDelete::Null => {
                             println!("\nClearing {} from {}...", field_name, item.coll);
                             // item: tri_order, field_name: order_a
-                            let collection = db.collection::<Document>(&item.coll);
+                            let collection = Self::coll::<Document>(db, &item.coll)?;
                             let tx = collection
                                 .update_many(
-                                    doc! { field_name: Some(id) },
+                                    Self::tenant_filter(&item.coll, tenant.as_ref(), doc! { field_name: Some(id) }),
                                     doc! {
                                         "$set": doc! { field_name: None::<ObjectId> }
                                     },
@@ -86,15 +164,15 @@ impl Tumongo {
                     continue;
                 }
                 println!("\n[{}] Deleting child {:?}", coll_name, item);
-                let collection = db.collection::<Document>(&item.coll);
-                let mut cursor = collection.find(doc! {field_name: id }).await?;
-                while let Ok(Some(dok)) = cursor.try_next().await {
+                let filter = Self::tenant_filter(&item.coll, tenant.as_ref(), doc! {field_name: id });
+                let children = Self::find_docs(db, &item.coll, filter, Some(&mut *sess)).await?;
+                for dok in children {
                     Box::pin(async {
                         Self::delete(
-                            &db,
+                            db,
                             &item.coll,
                             &dok.get_object_id("_id").unwrap(),
-                            &fk_fields,
+                            fk_fields,
                             sess,
                             true,
                         )
@@ -104,7 +182,7 @@ impl Tumongo {
                 }
             }
         }
-        let collection = db.collection::<Document>(coll_name);
+        let collection = Self::coll::<Document>(db, coll_name)?;
         if !is_child {
             println!("\nNow deleting {coll_name}...");
         }
@@ -114,15 +192,47 @@ impl Tumongo {
             .await?;
         Ok(())
     }
+    /// raw find on `coll_name`, in `sess` if given
+    pub(crate) async fn find_docs(
+        db: &Database,
+        coll_name: &str,
+        filter: Document,
+        sess: Option<&mut ClientSession>,
+    ) -> db::error::Result<Vec<Document>> {
+        let coll = Self::coll::<Document>(db, coll_name)?;
+        match sess {
+            Some(sess) => {
+                let mut cursor = coll.find(filter).session(&mut *sess).await?;
+                cursor.stream(&mut *sess).try_collect().await
+            }
+            None => coll.find(filter).await?.try_collect().await,
+        }
+    }
+
+    pub(crate) async fn find_one_doc(
+        db: &Database,
+        coll_name: &str,
+        filter: Document,
+        sess: Option<&mut ClientSession>,
+    ) -> db::error::Result<Option<Document>> {
+        let coll = Self::coll::<Document>(db, coll_name)?;
+        let query = coll.find_one(filter);
+        match sess {
+            Some(sess) => query.session(sess).await,
+            None => query.await,
+        }
+    }
+
     async fn populate_ref_fields(
-        _db: &Database,
+        ctx: &PopulateCtx<'_>,
         dok: SyncDoc,
         coll_name: &str,
         coll_names: Option<&[&str]>,
-        ref_fields: &FkFieldMap,
+        mut sess: Option<&mut ClientSession>,
     ) -> SyncDoc {
         // log!("[REF_F] {coll_name}");
-        if let Some(refs) = ref_fields.get(coll_name) {
+        if let Some(refs) = ctx.ref_fields.get(coll_name) {
+            let tenant = Self::tenant_of(coll_name, &*dok.lock().await);
             for reff in refs.iter() {
                 let ref_coll = reff.coll.as_str();
                 // log!("[REF] Populating [{ref_coll}] for [{coll_name}]...");
@@ -136,19 +246,33 @@ impl Tumongo {
                     continue;
                 }
                 let ref_id = ref_id.unwrap();
+                // a session can't be used on another connection, those reads go without
+                let mut sess = sess.as_deref_mut().filter(|_| Self::same_conn(coll_name, ref_coll));
 
-                let _dok = _db
-                    .collection::<Document>(&reff.coll)
-                    .find_one(doc! {
-                        "_id": ref_id
-                    })
-                    .await;
+                let _dok = Self::find_one_doc(
+                    ctx.db,
+                    &reff.coll,
+                    Self::live_filter(
+                        ref_coll,
+                        Self::tenant_filter(ref_coll, tenant.as_ref(), doc! {
+                            "_id": ref_id
+                        }),
+                    ),
+                    sess.as_deref_mut(),
+                )
+                .await;
                 if let Ok(Some(mut _dok)) = _dok {
                     // log!("[REF] [{ref_coll}] for [{coll_name}] id: {ref_id:?}");
                     let _dok = Arc::new(Mutex::new(_dok));
                     let dok_populated = Box::pin(async {
-                        Tumongo::populate_ref_fields(_db, _dok.clone(), ref_coll, None, ref_fields)
-                            .await
+                        Tumongo::populate_ref_fields(
+                            ctx,
+                            _dok.clone(),
+                            ref_coll,
+                            None,
+                            sess.as_deref_mut(),
+                        )
+                        .await
                     })
                     .await;
                     let dok_populated = dok_populated.lock().await.clone();
@@ -162,19 +286,19 @@ impl Tumongo {
     }
 
     async fn populate_fk_fields(
-        _db: &Database,
+        ctx: &PopulateCtx<'_>,
         dok: SyncDoc,
         coll_name: &str,
         coll_names: Option<&[&str]>,
-        fk_fields: &FkFieldMap,
-        ref_fields: &FkFieldMap,
         skip: &mut Vec<String>,
+        mut sess: Option<&mut ClientSession>,
     ) -> SyncDoc {
         // let mut ret = Map::new();
         // log!("[{coll_name}] {:?}", dok);
-        if let Some(fks) = fk_fields.get(coll_name) {
+        if let Some(fks) = ctx.fk_fields.get(coll_name) {
             // log!("GETTING DID...");
             let dok_id = dok.lock().await.get_object_id("_id").unwrap();
+            let tenant = Self::tenant_of(coll_name, &*dok.lock().await);
             // log!("DID: {:?}", dok_id);
             for fk in fks.iter() {
                 let fk_coll = fk.coll.as_str();
@@ -188,24 +312,31 @@ impl Tumongo {
                     continue;
                 }
                 let field = &fk.field_name;
-                let dok_cursor = _db
-                    .collection::<Document>(&fk.coll)
-                    .find(doc! {
-                        field: dok_id
-                    })
-                    .await;
-                if let Ok(mut dok_cursor) = dok_cursor {
+                let mut sess = sess.as_deref_mut().filter(|_| Self::same_conn(coll_name, fk_coll));
+                let child_doks = Self::find_docs(
+                    ctx.db,
+                    &fk.coll,
+                    Self::live_filter(
+                        fk_coll,
+                        Self::tenant_filter(fk_coll, tenant.as_ref(), doc! {
+                            field: dok_id
+                        }),
+                    ),
+                    sess.as_deref_mut(),
+                )
+                .await;
+                if let Ok(child_doks) = child_doks {
                     let mut doks: Vec<_> = vec![];
-                    while let Ok(Some(mut _dok)) = dok_cursor.try_next().await {
+                    for _dok in child_doks {
                         let _id = _dok.get_object_id("_id").unwrap();
                         // log!("[FK] [{fk_coll}] for [{coll_name}] id: {_id:?}");
                         let _dok = SyncDoc::new(Mutex::new(_dok));
                         let _dok = Self::populate_ref_fields(
-                            _db,
+                            ctx,
                             _dok.clone(),
                             fk_coll,
                             coll_names,
-                            ref_fields,
+                            sess.as_deref_mut(),
                         )
                         .await;
 
@@ -213,13 +344,12 @@ impl Tumongo {
                             // let dok = dok.clone();
                             async {
                                 Tumongo::populate_fk_fields(
-                                    _db,
+                                    ctx,
                                     _dok.clone(),
                                     fk_coll,
                                     None,
-                                    fk_fields,
-                                    ref_fields,
                                     skip,
+                                    sess.as_deref_mut(),
                                 )
                                 .await
                             }
@@ -257,24 +387,52 @@ impl Tumongo {
         coll_names: Option<&[&str]>,
         fk_fields: &FkFieldMap,
         ref_fields: &FkFieldMap,
+    ) -> Document {
+        Self::populate_in(_db, dok, coll_name, coll_names, fk_fields, ref_fields, None).await
+    }
+
+    /// [Tumongo::populate] with the reads in `sess`
+    pub async fn populate_with_session(
+        _db: &Database,
+        dok: Document,
+        coll_name: &str,
+        coll_names: Option<&[&str]>,
+        fk_fields: &FkFieldMap,
+        ref_fields: &FkFieldMap,
+        sess: &mut ClientSession,
+    ) -> Document {
+        Self::populate_in(_db, dok, coll_name, coll_names, fk_fields, ref_fields, Some(sess)).await
+    }
+
+    async fn populate_in(
+        _db: &Database,
+        dok: Document,
+        coll_name: &str,
+        coll_names: Option<&[&str]>,
+        fk_fields: &FkFieldMap,
+        ref_fields: &FkFieldMap,
+        mut sess: Option<&mut ClientSession>,
     ) -> Document {
         // populate foreign key referencing collections
         // log!("\nREF_FIELDS: {ref_fields:#?}");
         let dok = Arc::new(Mutex::new(dok));
+        let ctx = PopulateCtx {
+            db: _db,
+            fk_fields,
+            ref_fields,
+        };
 
         // populate foreign key referencing collections
         let dok = Self::populate_fk_fields(
-            _db,
+            &ctx,
             dok.clone(),
             coll_name,
             coll_names,
-            fk_fields,
-            ref_fields,
             &mut vec![],
+            sess.as_deref_mut(),
         )
         .await;
-        let dok =
-            Self::populate_ref_fields(_db, dok.clone(), coll_name, coll_names, ref_fields).await;
+        let dok = Self::populate_ref_fields(&ctx, dok.clone(), coll_name, coll_names, sess).await;
 
         dok.lock().await.clone()
     }
@@ -348,24 +506,22 @@ impl DateTime {
     }
 }
 
-pub fn mongo_url(offline: bool) -> String {
+pub fn mongo_url(offline: bool) -> Res<String> {
     let k = if offline {
         "MONGO_URL_LOCAL"
     } else {
         "MONGO_URL"
     };
-    env::var(k).expect("Failed to get MONG_URL from .env.")
+    env::var(k).map_err(|_| format!("{k} is not set").into())
 }
 
-pub async fn connect_db(url: &str, db_name: &str) -> db::error::Result<()> {
-    register!();
-    println!("\n[{db_name}] Connecting to [{url}]...");
-    let _cl = Client::with_uri_str(url).await?;
-    DB.set(_cl.database(db_name)).ok();
-    Ok(())
+/// single connection shorthand for [Tumongo::connect], sets the global `dbase()` on the first call
+pub async fn connect_db(url: &str, db_name: &str) -> Res<Connections> {
+    let config = TumongoConfig::builder().connection(DEFAULT_CONN, ConnOpts::new(url, db_name));
+    Tumongo::connect(&config).await
 }
 
+/// the default database of the first connect, panics if there was none
 pub fn dbase<'a>() -> &'a Database {
-    // register!();
-    DB.get().unwrap()
+    DB.get().expect("No database connected, call Tumongo::connect or connect_db first")
 }
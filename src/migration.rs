@@ -0,0 +1,201 @@
+use async_trait::async_trait;
+use futures_util::TryStreamExt;
+use mongodb::{
+    Database,
+    bson::{self, Bson, Document, doc},
+    error::{ErrorKind, WriteFailure},
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+use crate::{Res, Tumongo};
+
+/// applied migrations and the run lock
+pub const MIGRATIONS_COLL: &str = "tumongo_migrations";
+const LOCK_ID: &str = "lock";
+
+static MIGRATIONS: Lazy<Mutex<Vec<Arc<dyn Migration>>>> = Lazy::new(|| Mutex::new(vec![]));
+
+/// a versioned schema/data change, see [Tumongo::register_migration]
+#[async_trait]
+pub trait Migration: Send + Sync {
+    /// unique, migrations run in ascending order, e.g 20250904120000
+    fn version(&self) -> i64;
+    fn name(&self) -> String;
+    async fn up(&self, db: &Database) -> Res<()>;
+    async fn down(&self, db: &Database) -> Res<()>;
+}
+
+/// a row of [Tumongo::migration_status]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrationRecord {
+    pub version: i64,
+    pub name: String,
+    /// None if pending
+    pub applied_at: Option<bson::DateTime>,
+}
+
+impl Tumongo {
+    /// adds `migration` to the registry, versions must be unique
+    pub fn register_migration(migration: impl Migration + 'static) -> Res<()> {
+        let mut reg = MIGRATIONS.lock().expect("Failed to lock migration reg.");
+        let version = migration.version();
+        if reg.iter().any(|m| m.version() == version) {
+            return Err(format!("Migration version {version} is already registered").into());
+        }
+        reg.push(Arc::new(migration));
+        reg.sort_by_key(|m| m.version());
+        Ok(())
+    }
+
+    pub(crate) fn migrations() -> Vec<Arc<dyn Migration>> {
+        MIGRATIONS.lock().expect("Failed to lock migration reg.").clone()
+    }
+
+    async fn applied_migrations(db: &Database) -> Res<Vec<MigrationRecord>> {
+        let records = db
+            .collection::<MigrationRecord>(MIGRATIONS_COLL)
+            .find(doc! {"version": {"$exists": true}})
+            .sort(doc! {"version": 1})
+            .await?
+            .try_collect()
+            .await?;
+        Ok(records)
+    }
+
+    /// every registered or applied migration, oldest first
+    pub async fn migration_status(db: &Database) -> Res<Vec<MigrationRecord>> {
+        let mut records = Self::applied_migrations(db).await?;
+        for m in Self::migrations() {
+            if !records.iter().any(|r| r.version == m.version()) {
+                records.push(MigrationRecord {
+                    version: m.version(),
+                    name: m.name(),
+                    applied_at: None,
+                });
+            }
+        }
+        records.sort_by_key(|r| r.version);
+        Ok(records)
+    }
+
+    /// fails if another run holds the lock
+    async fn lock_migrations(db: &Database) -> Res<()> {
+        let res = db
+            .collection::<Document>(MIGRATIONS_COLL)
+            .insert_one(doc! {"_id": LOCK_ID, "at": bson::DateTime::now()})
+            .await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(err) if matches!(&*err.kind, ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == 11000) => {
+                Err("Migrations are locked by another run, see Tumongo::unlock_migrations".into())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// releases the lock, e.g after a run crashed while holding it
+    pub async fn unlock_migrations(db: &Database) -> Res<()> {
+        db.collection::<Document>(MIGRATIONS_COLL)
+            .delete_one(doc! {"_id": LOCK_ID})
+            .await?;
+        Ok(())
+    }
+
+    /// runs the pending migrations in order, returns the applied versions.
+    ///
+    /// stops at the first failure, the ones before it stay applied
+    pub async fn migrate(db: &Database) -> Res<Vec<i64>> {
+        Self::lock_migrations(db).await?;
+        let res = Self::migrate_locked(db).await;
+        Self::unlock_migrations(db).await?;
+        res
+    }
+
+    async fn migrate_locked(db: &Database) -> Res<Vec<i64>> {
+        let applied = Self::applied_migrations(db).await?;
+        let coll = db.collection::<MigrationRecord>(MIGRATIONS_COLL);
+        let mut done = vec![];
+        for m in Self::migrations() {
+            let version = m.version();
+            if applied.iter().any(|r| r.version == version) {
+                continue;
+            }
+            println!("\n[migrate] Applying {version} {}...", m.name());
+            m.up(db)
+                .await
+                .map_err(|err| format!("Migration {version} {} failed: {err}", m.name()))?;
+            coll.insert_one(MigrationRecord {
+                version,
+                name: m.name(),
+                applied_at: Some(bson::DateTime::now()),
+            })
+            .await?;
+            done.push(version);
+        }
+        Ok(done)
+    }
+
+    /// reverts the last `n` applied migrations, newest first, returns the reverted versions
+    pub async fn rollback(db: &Database, n: usize) -> Res<Vec<i64>> {
+        Self::lock_migrations(db).await?;
+        let res = Self::rollback_locked(db, n).await;
+        Self::unlock_migrations(db).await?;
+        res
+    }
+
+    async fn rollback_locked(db: &Database, n: usize) -> Res<Vec<i64>> {
+        let applied = Self::applied_migrations(db).await?;
+        let migrations = Self::migrations();
+        let coll = db.collection::<Document>(MIGRATIONS_COLL);
+        let mut done = vec![];
+        for record in applied.iter().rev().take(n) {
+            let version = record.version;
+            let Some(m) = migrations.iter().find(|m| m.version() == version) else {
+                return Err(format!("Applied migration {version} {} is not registered", record.name).into());
+            };
+            println!("\n[rollback] Reverting {version} {}...", m.name());
+            m.down(db)
+                .await
+                .map_err(|err| format!("Rollback of {version} {} failed: {err}", m.name()))?;
+            coll.delete_one(doc! {"version": Bson::Int64(version)}).await?;
+            done.push(version);
+        }
+        Ok(done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Noop(i64);
+
+    #[async_trait]
+    impl Migration for Noop {
+        fn version(&self) -> i64 {
+            self.0
+        }
+        fn name(&self) -> String {
+            format!("noop_{}", self.0)
+        }
+        async fn up(&self, _db: &Database) -> Res<()> {
+            Ok(())
+        }
+        async fn down(&self, _db: &Database) -> Res<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn registry_is_sorted_and_unique() {
+        Tumongo::register_migration(Noop(20250903)).unwrap();
+        Tumongo::register_migration(Noop(20250901)).unwrap();
+        Tumongo::register_migration(Noop(20250902)).unwrap();
+        assert!(Tumongo::register_migration(Noop(20250902)).is_err());
+
+        let versions: Vec<i64> = Tumongo::migrations().iter().map(|m| m.version()).collect();
+        assert_eq!(versions, vec![20250901, 20250902, 20250903]);
+    }
+}
@@ -0,0 +1,163 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use mongodb::{
+    Database,
+    bson::{Bson, Document, doc},
+};
+use serde::Serialize;
+
+use crate::{Deleted, FindOpts, Model, Res, Tumongo};
+
+/// one page of [Tumongo::paginate]
+#[derive(Debug, Clone, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// matching docs over all pages
+    pub total: u64,
+    /// 1 based
+    pub page: u64,
+    pub pages: u64,
+}
+
+/// one page of [Tumongo::paginate_after]
+#[derive(Debug, Clone, Serialize)]
+pub struct CursorPage<T> {
+    pub items: Vec<T>,
+    /// pass back in to get the next page, None on the last page
+    pub next: Option<String>,
+}
+
+fn encode_cursor(key: Bson, _id: Bson) -> Res<String> {
+    let mut bytes = vec![];
+    doc! {"k": key, "id": _id}.to_writer(&mut bytes)?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+fn decode_cursor(cursor: &str) -> Res<(Bson, Bson)> {
+    let bytes = URL_SAFE_NO_PAD.decode(cursor)?;
+    let mut dok = Document::from_reader(&bytes[..])?;
+    match (dok.remove("k"), dok.remove("id")) {
+        (Some(key), Some(_id)) => Ok((key, _id)),
+        _ => Err(format!("Invalid page cursor {cursor}").into()),
+    }
+}
+
+impl Tumongo {
+    /// offset pagination sorted by _id, `page` starts at 1
+    pub async fn paginate<M: Model>(
+        db: &Database,
+        filter: Document,
+        page: u64,
+        per_page: u64,
+    ) -> Res<Page<M>> {
+        if page == 0 || per_page == 0 {
+            return Err("page and per_page should be at least 1".into());
+        }
+        Self::check_tenant::<M>(&filter)?;
+        let total = Self::coll::<Document>(db, &M::coll_name())?
+            .count_documents(Self::scope_filter::<M>(filter.clone(), Deleted::Exclude))
+            .await?;
+        let opts = FindOpts {
+            skip: Some((page - 1) * per_page),
+            limit: Some(per_page as i64),
+            sort: Some(doc! {"_id": 1}),
+            ..Default::default()
+        };
+        let items = Self::find(db, filter, opts).await?;
+        Ok(Page {
+            items,
+            total,
+            page,
+            pages: total.div_ceil(per_page),
+        })
+    }
+
+    /// keyset pagination on `sort_field` ("-field" for descending) with _id breaking ties.
+    ///
+    /// stable under inserts/deletes, unlike skip based paging
+    pub async fn paginate_after<M: Model>(
+        db: &Database,
+        filter: Document,
+        sort_field: &str,
+        cursor: Option<&str>,
+        limit: i64,
+    ) -> Res<CursorPage<M>> {
+        if limit < 1 {
+            return Err("limit should be at least 1".into());
+        }
+        let (field, dir) = match sort_field.strip_prefix('-') {
+            Some(field) => (field, -1),
+            None => (sort_field, 1),
+        };
+        let field = if field == "id" { "_id" } else { field };
+        let op = if dir == 1 { "$gt" } else { "$lt" };
+
+        let mut filter = filter;
+        if let Some(cursor) = cursor {
+            let (key, _id) = decode_cursor(cursor)?;
+            let after = if field == "_id" {
+                doc! {"_id": {op: _id}}
+            } else {
+                doc! {"$or": [
+                    {field: {op: key.clone()}},
+                    {field: key, "_id": {op: _id}}
+                ]}
+            };
+            filter = if filter.is_empty() {
+                after
+            } else {
+                doc! {"$and": [filter, after]}
+            };
+        }
+
+        let mut sort = doc! {field: dir};
+        sort.insert("_id", dir);
+        let opts = FindOpts {
+            limit: Some(limit + 1),
+            sort: Some(sort),
+            ..Default::default()
+        };
+        let mut items: Vec<M> = Self::find(db, filter, opts).await?;
+
+        let mut next = None;
+        if items.len() as i64 > limit {
+            items.truncate(limit as usize);
+            if let Some(last) = items.last() {
+                let dok = last.to_doc();
+                let key = dok.get(field).cloned().unwrap_or(Bson::Null);
+                let _id = dok.get("_id").cloned().unwrap_or(Bson::Null);
+                next = Some(encode_cursor(key, _id)?);
+            }
+        }
+        Ok(CursorPage { items, next })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::{DateTime, oid::ObjectId};
+
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips() {
+        let _id = ObjectId::new();
+        let key = Bson::DateTime(DateTime::from_millis(1_700_000_000_000));
+        let cursor = encode_cursor(key.clone(), _id.into()).unwrap();
+        assert!(cursor.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(decode_cursor(&cursor).unwrap(), (key, Bson::ObjectId(_id)));
+    }
+
+    #[test]
+    fn cursor_keeps_null_keys() {
+        let cursor = encode_cursor(Bson::Null, Bson::Int32(7)).unwrap();
+        assert_eq!(decode_cursor(&cursor).unwrap(), (Bson::Null, Bson::Int32(7)));
+    }
+
+    #[test]
+    fn invalid_cursors_fail() {
+        assert!(decode_cursor("not a cursor!").is_err());
+        let mut bytes = vec![];
+        doc! {"k": 1}.to_writer(&mut bytes).unwrap();
+        assert!(decode_cursor(&URL_SAFE_NO_PAD.encode(bytes)).is_err());
+    }
+}
@@ -0,0 +1,310 @@
+use mongodb::{
+    Database,
+    bson::{self, Bson, Document, doc},
+};
+use serde::Serialize;
+use std::marker::PhantomData;
+
+use crate::{Deleted, DeserializeMode, FindOpts, Found, Model, Res, Tumongo};
+
+fn to_bson<V: Serialize>(v: &V) -> Bson {
+    bson::to_bson(v).expect("Failed to convert query value to bson")
+}
+
+/// typed path to a field of model `M` holding a `T`,
+/// generated per model in the `<model>_fields` module
+pub struct Field<M, T> {
+    name: &'static str,
+    _model: PhantomData<fn() -> (M, T)>,
+}
+
+impl<M, T> Clone for Field<M, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<M, T> Copy for Field<M, T> {}
+
+impl<M, T> Field<M, T> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            _model: PhantomData,
+        }
+    }
+    /// name of the field in the stored doc
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    /// "$name", for referring to the field in aggregation expressions
+    pub fn path(&self) -> String {
+        format!("${}", self.name)
+    }
+    pub fn exists(&self, yes: bool) -> Filter<M> {
+        let name = self.name;
+        Filter::new(doc! { name: { "$exists": yes } })
+    }
+    pub fn asc(&self) -> Sort<M> {
+        let name = self.name;
+        Sort::new(doc! { name: 1 })
+    }
+    pub fn desc(&self) -> Sort<M> {
+        let name = self.name;
+        Sort::new(doc! { name: -1 })
+    }
+}
+
+impl<M, T: Serialize> Field<M, T> {
+    fn op(&self, op: &str, v: Bson) -> Filter<M> {
+        let name = self.name;
+        Filter::new(doc! { name: { op: v } })
+    }
+    pub fn eq(&self, v: impl Into<T>) -> Filter<M> {
+        let name = self.name;
+        Filter::new(doc! { name: to_bson(&v.into()) })
+    }
+    pub fn ne(&self, v: impl Into<T>) -> Filter<M> {
+        self.op("$ne", to_bson(&v.into()))
+    }
+    pub fn gt(&self, v: impl Into<T>) -> Filter<M> {
+        self.op("$gt", to_bson(&v.into()))
+    }
+    pub fn gte(&self, v: impl Into<T>) -> Filter<M> {
+        self.op("$gte", to_bson(&v.into()))
+    }
+    pub fn lt(&self, v: impl Into<T>) -> Filter<M> {
+        self.op("$lt", to_bson(&v.into()))
+    }
+    pub fn lte(&self, v: impl Into<T>) -> Filter<M> {
+        self.op("$lte", to_bson(&v.into()))
+    }
+    pub fn in_<V: Into<T>>(&self, vals: impl IntoIterator<Item = V>) -> Filter<M> {
+        let vals: Vec<Bson> = vals.into_iter().map(|v| to_bson(&v.into())).collect();
+        self.op("$in", Bson::Array(vals))
+    }
+    pub fn nin<V: Into<T>>(&self, vals: impl IntoIterator<Item = V>) -> Filter<M> {
+        let vals: Vec<Bson> = vals.into_iter().map(|v| to_bson(&v.into())).collect();
+        self.op("$nin", Bson::Array(vals))
+    }
+}
+
+impl<M, E: Serialize> Field<M, Vec<E>> {
+    /// array field holds `v`
+    pub fn contains(&self, v: impl Into<E>) -> Filter<M> {
+        let name = self.name;
+        Filter::new(doc! { name: to_bson(&v.into()) })
+    }
+}
+
+impl<M> Field<M, String> {
+    /// `$regex` match, options like "i"
+    pub fn regex(&self, pattern: &str, options: &str) -> Filter<M> {
+        let name = self.name;
+        Filter::new(doc! { name: { "$regex": pattern, "$options": options } })
+    }
+}
+
+/// filter on model `M`, turns into a plain Document
+pub struct Filter<M> {
+    dok: Document,
+    _model: PhantomData<fn() -> M>,
+}
+
+impl<M> Clone for Filter<M> {
+    fn clone(&self) -> Self {
+        Self::new(self.dok.clone())
+    }
+}
+
+impl<M> std::fmt::Debug for Filter<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.dok.fmt(f)
+    }
+}
+
+impl<M> Default for Filter<M> {
+    fn default() -> Self {
+        Self::new(Document::new())
+    }
+}
+
+impl<M> Filter<M> {
+    /// escape hatch for conditions the typed api doesn't cover
+    pub fn new(dok: Document) -> Self {
+        Self {
+            dok,
+            _model: PhantomData,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.dok.is_empty()
+    }
+    fn parts(self, op: &str) -> Vec<Bson> {
+        match self.dok.get_array(op) {
+            Ok(parts) if self.dok.len() == 1 => parts.clone(),
+            _ => vec![Bson::Document(self.dok)],
+        }
+    }
+    fn join(self, op: &str, other: Filter<M>) -> Self {
+        if self.is_empty() {
+            return other;
+        }
+        if other.is_empty() {
+            return self;
+        }
+        let mut parts = self.parts(op);
+        parts.extend(other.parts(op));
+        Self::new(doc! { op: parts })
+    }
+    pub fn and(self, other: Filter<M>) -> Self {
+        self.join("$and", other)
+    }
+    pub fn or(self, other: Filter<M>) -> Self {
+        self.join("$or", other)
+    }
+    pub fn into_doc(self) -> Document {
+        self.dok
+    }
+}
+
+impl<M> From<Filter<M>> for Document {
+    fn from(f: Filter<M>) -> Self {
+        f.dok
+    }
+}
+
+/// sort spec on model `M`, from `Field::asc`/`Field::desc`
+pub struct Sort<M> {
+    dok: Document,
+    _model: PhantomData<fn() -> M>,
+}
+
+impl<M> Sort<M> {
+    fn new(dok: Document) -> Self {
+        Self {
+            dok,
+            _model: PhantomData,
+        }
+    }
+    /// sorts by `other` when this sort ties
+    pub fn then(mut self, other: Sort<M>) -> Self {
+        self.dok.extend(other.dok);
+        self
+    }
+    pub fn into_doc(self) -> Document {
+        self.dok
+    }
+}
+
+/// typed `find` builder, see the generated `Model::query()`
+pub struct Query<M> {
+    filter: Filter<M>,
+    opts: FindOpts,
+}
+
+impl<M: Model> Default for Query<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: Model> Query<M> {
+    pub fn new() -> Self {
+        Self {
+            filter: Filter::default(),
+            opts: FindOpts::default(),
+        }
+    }
+    pub fn filter(mut self, f: Filter<M>) -> Self {
+        self.filter = self.filter.and(f);
+        self
+    }
+    pub fn and(self, f: Filter<M>) -> Self {
+        self.filter(f)
+    }
+    pub fn or(mut self, f: Filter<M>) -> Self {
+        self.filter = self.filter.or(f);
+        self
+    }
+    pub fn sort(mut self, s: Sort<M>) -> Self {
+        let sort = self.opts.sort.get_or_insert_with(Document::new);
+        sort.extend(s.dok);
+        self
+    }
+    pub fn skip(mut self, skip: u64) -> Self {
+        self.opts.skip = Some(skip);
+        self
+    }
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.opts.limit = Some(limit);
+        self
+    }
+    /// soft delete scope, ignored for other models
+    pub fn deleted(mut self, deleted: Deleted) -> Self {
+        self.opts.deleted = deleted;
+        self
+    }
+    pub fn mode(mut self, mode: DeserializeMode) -> Self {
+        self.opts.mode = mode;
+        self
+    }
+    /// the filter as sent to the server, before the soft delete scope
+    pub fn filter_doc(&self) -> Document {
+        self.filter.dok.clone()
+    }
+    pub fn sort_doc(&self) -> Option<Document> {
+        self.opts.sort.clone()
+    }
+    pub async fn find(self, db: &Database) -> Res<Vec<M>> {
+        Tumongo::find(db, self.filter.dok, self.opts).await
+    }
+    pub async fn find_one(self, db: &Database) -> Res<M> {
+        Tumongo::find_one(db, self.filter.dok, self.opts).await
+    }
+    /// also returns the docs that failed to deserialize, see [DeserializeMode]
+    pub async fn find_all(self, db: &Database) -> Res<Found<M>> {
+        Tumongo::find_all(db, self.filter.dok, self.opts).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Person;
+    const NAME: Field<Person, String> = Field::new("name");
+    const AGE: Field<Person, i64> = Field::new("age");
+
+    #[test]
+    fn and_joins_two_filters() {
+        let f = NAME.eq("a").and(AGE.gt(3));
+        assert_eq!(f.into_doc(), doc! {"$and": [{"name": "a"}, {"age": {"$gt": 3_i64}}]});
+    }
+
+    #[test]
+    fn join_flattens_the_same_op() {
+        let f = NAME.eq("a").or(NAME.eq("b")).or(NAME.eq("c"));
+        assert_eq!(f.into_doc(), doc! {"$or": [{"name": "a"}, {"name": "b"}, {"name": "c"}]});
+    }
+
+    #[test]
+    fn join_nests_other_ops() {
+        let f = NAME.eq("a").or(NAME.eq("b")).and(AGE.lt(9));
+        assert_eq!(
+            f.into_doc(),
+            doc! {"$and": [{"$or": [{"name": "a"}, {"name": "b"}]}, {"age": {"$lt": 9_i64}}]}
+        );
+    }
+
+    #[test]
+    fn join_skips_empty_filters() {
+        let f = Filter::default().and(NAME.eq("a")).and(Filter::default());
+        assert_eq!(f.into_doc(), doc! {"name": "a"});
+    }
+
+    #[test]
+    fn sort_then_keeps_order() {
+        let s = AGE.desc().then(NAME.asc());
+        assert_eq!(s.into_doc(), doc! {"age": -1, "name": 1});
+    }
+}
@@ -0,0 +1,168 @@
+use mongodb::{
+    ClientSession, Collection, Database,
+    options::{
+        Acknowledgment, CollectionOptions, ReadConcern, ReadPreference, SelectionCriteria,
+        WriteConcern,
+    },
+};
+
+use crate::{COLL_ROUTES, CollRoute, Tumongo, db};
+
+impl CollRoute {
+    /// the collection options for the read/write settings, values are checked by the derive
+    pub fn options(&self) -> CollectionOptions {
+        let mut opts = CollectionOptions::builder().build();
+        opts.selection_criteria = self.read_preference.as_deref().map(|pref| {
+            let pref = match pref {
+                "primary_preferred" => ReadPreference::PrimaryPreferred { options: None },
+                "secondary" => ReadPreference::Secondary { options: None },
+                "secondary_preferred" => ReadPreference::SecondaryPreferred { options: None },
+                "nearest" => ReadPreference::Nearest { options: None },
+                _ => ReadPreference::Primary,
+            };
+            SelectionCriteria::ReadPreference(pref)
+        });
+        opts.read_concern = self.read_concern.as_deref().map(|level| match level {
+            "local" => ReadConcern::local(),
+            "available" => ReadConcern::available(),
+            "majority" => ReadConcern::majority(),
+            "linearizable" => ReadConcern::linearizable(),
+            "snapshot" => ReadConcern::snapshot(),
+            level => ReadConcern::custom(level),
+        });
+        opts.write_concern = self.write_concern.as_deref().map(|w| {
+            let w = match w.parse::<u32>() {
+                Ok(n) => Acknowledgment::Nodes(n),
+                Err(_) => Acknowledgment::from(w),
+            };
+            WriteConcern::builder().w(w).build()
+        });
+        opts
+    }
+
+    fn has_options(&self) -> bool {
+        self.read_preference.is_some() || self.read_concern.is_some() || self.write_concern.is_some()
+    }
+}
+
+impl Tumongo {
+    /// the database `coll_name` lives in, from its `#[tumongo(conn = "..", db = "..")]`.
+    ///
+    /// `conn` picks a connection of [Tumongo::conns], `db` a database on that connection's
+    /// client (or `db`'s client without `conn`). unrouted collections stay in `db`.
+    /// fails if `conn` isn't connected, it never falls back to another server
+    pub fn route_db(db: &Database, coll_name: &str) -> db::error::Result<Database> {
+        let Some(route) = Self::route(coll_name) else {
+            return Ok(db.clone());
+        };
+        let base = match &route.conn {
+            Some(conn) => Self::conns()
+                .and_then(|conns| conns.get(conn))
+                .ok_or_else(|| {
+                    // a driver error so the session based paths can `?` it
+                    db::error::Error::custom(format!(
+                        "[{coll_name}] No connection named {conn}, call Tumongo::connect with it first"
+                    ))
+                })?
+                .clone(),
+            None => db.clone(),
+        };
+        Ok(match &route.db {
+            Some(db_name) => base.client().database(db_name),
+            None => base,
+        })
+    }
+
+    /// true if `a` and `b` are on the same connection, so a session of one works on the other
+    pub(crate) fn same_conn(a: &str, b: &str) -> bool {
+        let conn = |coll_name| Self::route(coll_name).and_then(|route| route.conn.as_deref());
+        conn(a) == conn(b)
+    }
+
+    /// a new session for `child_coll` if it's on another connection than `coll_name`,
+    /// None if a session of `coll_name` works for it too.
+    ///
+    /// sessions only work on their own client, so cascades into other connections
+    /// aren't part of the caller's transaction
+    pub(crate) async fn child_session(
+        db: &Database,
+        coll_name: &str,
+        child_coll: &str,
+    ) -> db::error::Result<Option<ClientSession>> {
+        if Self::same_conn(coll_name, child_coll) {
+            return Ok(None);
+        }
+        Ok(Some(Self::route_db(db, child_coll)?.client().start_session().await?))
+    }
+
+    fn route(coll_name: &str) -> Option<&'static CollRoute> {
+        COLL_ROUTES.get().and_then(|routes| routes.get(coll_name))
+    }
+
+    /// `coll_name` in its routed database with the model's read preference and concerns
+    pub fn coll<T: Send + Sync>(db: &Database, coll_name: &str) -> db::error::Result<Collection<T>> {
+        let db = Self::route_db(db, coll_name)?;
+        Ok(match Self::route(coll_name) {
+            Some(route) if route.has_options() => db.collection_with_options(coll_name, route.options()),
+            _ => db.collection::<T>(coll_name),
+        })
+    }
+
+    /// [Tumongo::coll] with the options set in `opts` taking precedence over the model's
+    pub fn coll_with<T: Send + Sync>(
+        db: &Database,
+        coll_name: &str,
+        opts: CollectionOptions,
+    ) -> db::error::Result<Collection<T>> {
+        let mut merged = Self::route(coll_name)
+            .map(|route| route.options())
+            .unwrap_or_else(|| CollectionOptions::builder().build());
+        if opts.selection_criteria.is_some() {
+            merged.selection_criteria = opts.selection_criteria;
+        }
+        if opts.read_concern.is_some() {
+            merged.read_concern = opts.read_concern;
+        }
+        if opts.write_concern.is_some() {
+            merged.write_concern = opts.write_concern;
+        }
+        Ok(Self::route_db(db, coll_name)?.collection_with_options(coll_name, merged))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_concern(w: &str) -> Option<Acknowledgment> {
+        let route = CollRoute {
+            write_concern: Some(w.to_string()),
+            ..Default::default()
+        };
+        route.options().write_concern.and_then(|wc| wc.w)
+    }
+
+    #[test]
+    fn write_concerns() {
+        assert_eq!(write_concern("majority"), Some(Acknowledgment::Majority));
+        assert_eq!(write_concern("2"), Some(Acknowledgment::Nodes(2)));
+        assert_eq!(write_concern("eu_dcs"), Some(Acknowledgment::Custom("eu_dcs".into())));
+        assert!(CollRoute::default().options().write_concern.is_none());
+    }
+
+    #[test]
+    fn read_settings() {
+        let route = CollRoute {
+            read_preference: Some("secondary_preferred".into()),
+            read_concern: Some("majority".into()),
+            ..Default::default()
+        };
+        let opts = route.options();
+        assert!(matches!(
+            opts.selection_criteria,
+            Some(SelectionCriteria::ReadPreference(ReadPreference::SecondaryPreferred { .. }))
+        ));
+        assert_eq!(opts.read_concern, Some(ReadConcern::majority()));
+        assert!(route.has_options() && !CollRoute::default().has_options());
+    }
+}
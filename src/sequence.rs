@@ -0,0 +1,37 @@
+use mongodb::{
+    ClientSession, Database,
+    bson::{Bson, Document, doc},
+    options::ReturnDocument,
+};
+
+use crate::{Tumongo, db};
+
+/// holds the counters behind #[tumongo(sequence = "..")] fields
+pub const COUNTERS_COLL: &str = "tumongo_counters";
+
+impl Tumongo {
+    /// atomically bumps and returns the counter `name`, starting at 1.
+    ///
+    /// each `scope` value (e.g a company_id) gets its own counter
+    pub async fn next_sequence(
+        db: &Database,
+        sess: &mut ClientSession,
+        name: &str,
+        scope: Option<Bson>,
+    ) -> db::error::Result<i64> {
+        let key = match scope {
+            Some(scope) => doc! {"name": name, "scope": scope},
+            None => doc! {"name": name},
+        };
+        let counter = db
+            .collection::<Document>(COUNTERS_COLL)
+            .find_one_and_update(doc! {"_id": key}, doc! {"$inc": {"value": 1_i64}})
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .session(&mut *sess)
+            .await?;
+        Ok(counter
+            .and_then(|c| c.get_i64("value").ok())
+            .unwrap_or(1))
+    }
+}
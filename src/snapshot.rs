@@ -0,0 +1,78 @@
+use mongodb::bson::Document;
+
+use crate::Tumongo;
+
+/// last loaded/saved state of a model, used for dirty tracking.
+///
+/// add it to a model as `#[serde(skip)] #[tumongo(snapshot)] pub snapshot: tumongo::Snapshot`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot(Option<Document>);
+
+impl Snapshot {
+    pub fn get(&self) -> Option<&Document> {
+        self.0.as_ref()
+    }
+    pub fn set(&mut self, dok: Document) {
+        self.0.replace(dok);
+    }
+    pub fn clear(&mut self) {
+        self.0 = None;
+    }
+}
+
+impl Tumongo {
+    /// returns ($set, $unset) docs needed to turn `before` into `after`.
+    ///
+    /// without a `before` everything in `after` is considered changed
+    pub fn diff(before: Option<&Document>, after: &Document) -> (Document, Document) {
+        let mut set = Document::new();
+        let mut unset = Document::new();
+        for (k, v) in after.iter() {
+            if k == "_id" {
+                continue;
+            }
+            if before.and_then(|b| b.get(k)) != Some(v) {
+                set.insert(k.to_owned(), v.clone());
+            }
+        }
+        if let Some(before) = before {
+            for k in before.keys() {
+                if k != "_id" && !after.contains_key(k) {
+                    unset.insert(k.to_owned(), "");
+                }
+            }
+        }
+        (set, unset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+
+    use super::*;
+
+    #[test]
+    fn diff_without_before_sets_everything_but_id() {
+        let after = doc! {"_id": 1, "name": "a", "age": 3};
+        let (set, unset) = Tumongo::diff(None, &after);
+        assert_eq!(set, doc! {"name": "a", "age": 3});
+        assert!(unset.is_empty());
+    }
+
+    #[test]
+    fn diff_sets_changed_and_unsets_removed() {
+        let before = doc! {"_id": 1, "name": "a", "age": 3, "alias": "x"};
+        let after = doc! {"_id": 2, "name": "b", "age": 3, "city": "c"};
+        let (set, unset) = Tumongo::diff(Some(&before), &after);
+        assert_eq!(set, doc! {"name": "b", "city": "c"});
+        assert_eq!(unset, doc! {"alias": ""});
+    }
+
+    #[test]
+    fn diff_of_equal_docs_is_empty() {
+        let dok = doc! {"name": "a", "tags": ["x", "y"], "addr": {"city": "c"}};
+        let (set, unset) = Tumongo::diff(Some(&dok), &dok.clone());
+        assert!(set.is_empty() && unset.is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+use mongodb::{
+    ClientSession, Database,
+    bson::{self, Bson, Document, doc, oid::ObjectId},
+};
+use std::{marker::PhantomData, str::FromStr};
+
+use crate::{DELETED_AT, Deleted, FindOpts, FkFieldMap, Model, OnDelete, Res, SOFT_DELETE_COLLS, Tumongo, db};
+
+/// `find`/`find_one` for a soft delete model with a non-default [Deleted] scope,
+/// see the generated `with_deleted()` and `only_deleted()`
+pub struct DeletedScope<M> {
+    deleted: Deleted,
+    _model: PhantomData<M>,
+}
+
+impl<M: Model> DeletedScope<M> {
+    pub fn new(deleted: Deleted) -> Self {
+        Self {
+            deleted,
+            _model: PhantomData,
+        }
+    }
+
+    pub async fn find(
+        &self,
+        _db: &Database,
+        filter: Document,
+        skip: Option<u64>,
+        limit: Option<i64>,
+        sort: Option<Document>,
+    ) -> Res<Vec<M>> {
+        let opts = FindOpts {
+            skip,
+            limit,
+            sort,
+            deleted: self.deleted,
+            ..Default::default()
+        };
+        Tumongo::find(_db, filter, opts).await
+    }
+
+    pub async fn find_one(&self, _db: &Database, filter: Document) -> Res<M> {
+        let opts = FindOpts {
+            deleted: self.deleted,
+            ..Default::default()
+        };
+        Tumongo::find_one(_db, filter, opts).await
+    }
+}
+
+impl Tumongo {
+    pub fn is_soft_delete(coll_name: &str) -> bool {
+        SOFT_DELETE_COLLS
+            .get()
+            .is_some_and(|colls| colls.iter().any(|c| c == coll_name))
+    }
+
+    /// hides soft deleted docs of `coll_name`, used when following relations
+    pub(crate) fn live_filter(coll_name: &str, filter: Document) -> Document {
+        if Self::is_soft_delete(coll_name) {
+            Deleted::Exclude.apply(filter)
+        } else {
+            filter
+        }
+    }
+
+    /// ids of the `on_delete = "cascade"` children of `coll_name` matching `filter`, per child collection
+    async fn cascade_children(
+        db: &Database,
+        coll_name: &str,
+        id: &ObjectId,
+        fk_fields: &FkFieldMap,
+        filter: Document,
+        sess: &mut ClientSession,
+    ) -> db::error::Result<Vec<(String, Vec<ObjectId>)>> {
+        let mut children = vec![];
+        if let Some(child_colls) = fk_fields.get(coll_name) {
+            let tenant = Self::stored_tenant(db, coll_name, id, Some(&mut *sess)).await?;
+            for item in child_colls {
+                let on_delete = item
+                    .on_delete
+                    .as_ref()
+                    .and_then(|s| OnDelete::from_str(s).ok());
+                if on_delete != Some(OnDelete::Cascade) {
+                    continue;
+                }
+                let mut child_filter = Self::tenant_filter(&item.coll, tenant.as_ref(), filter.clone());
+                child_filter.insert(&item.field_name, *id);
+                let mut own_sess = Self::child_session(db, coll_name, &item.coll).await?;
+                let sess = match own_sess.as_mut() {
+                    Some(own_sess) => own_sess,
+                    None => &mut *sess,
+                };
+                let doks = Self::find_docs(db, &item.coll, child_filter, Some(sess)).await?;
+                let ids = doks
+                    .iter()
+                    .filter_map(|d| d.get_object_id("_id").ok())
+                    .collect();
+                children.push((item.coll.clone(), ids));
+            }
+        }
+        Ok(children)
+    }
+
+    /// sets `deleted_at` on the doc and its `on_delete = "cascade"` children.
+    ///
+    /// `on_delete = "null"` children are left alone so a restore brings everything back.
+    /// `register!()` refuses cascade children without soft delete, they'd be left alone too
+    pub async fn soft_delete(
+        db: &Database,
+        coll_name: &str,
+        id: &ObjectId,
+        fk_fields: &FkFieldMap,
+        sess: &mut ClientSession,
+        at: bson::DateTime,
+    ) -> db::error::Result<()> {
+        let filter = doc! { DELETED_AT: Bson::Null };
+        for (child_coll, ids) in Self::cascade_children(db, coll_name, id, fk_fields, filter, sess).await? {
+            if !Self::is_soft_delete(&child_coll) {
+                continue;
+            }
+            let mut own_sess = Self::child_session(db, coll_name, &child_coll).await?;
+            let sess = match own_sess.as_mut() {
+                Some(own_sess) => own_sess,
+                None => &mut *sess,
+            };
+            for child_id in ids {
+                Box::pin(Self::soft_delete(db, &child_coll, &child_id, fk_fields, sess, at)).await?;
+            }
+        }
+        println!("\nSoft deleting {coll_name} {id}...");
+        Self::coll::<Document>(db, coll_name)?
+            .update_one(doc! {"_id": id}, doc! {"$set": { DELETED_AT: at }})
+            .session(&mut *sess)
+            .await?;
+        Ok(())
+    }
+
+    /// undoes [Tumongo::soft_delete], children are only restored if they were deleted along with the doc
+    pub async fn restore(
+        db: &Database,
+        coll_name: &str,
+        id: &ObjectId,
+        fk_fields: &FkFieldMap,
+        sess: &mut ClientSession,
+        at: bson::DateTime,
+    ) -> db::error::Result<()> {
+        let filter = doc! { DELETED_AT: at };
+        for (child_coll, ids) in Self::cascade_children(db, coll_name, id, fk_fields, filter, sess).await? {
+            let mut own_sess = Self::child_session(db, coll_name, &child_coll).await?;
+            let sess = match own_sess.as_mut() {
+                Some(own_sess) => own_sess,
+                None => &mut *sess,
+            };
+            for child_id in ids {
+                Box::pin(Self::restore(db, &child_coll, &child_id, fk_fields, sess, at)).await?;
+            }
+        }
+        Self::coll::<Document>(db, coll_name)?
+            .update_one(doc! {"_id": id}, doc! {"$unset": { DELETED_AT: "" }})
+            .session(&mut *sess)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,313 @@
+use mongodb::{
+    ClientSession, Database,
+    bson::{Bson, Document, doc, oid::ObjectId},
+};
+
+use crate::{Aggregate, COLL_ROUTES, FindOpts, HistoryRecord, Model, Res, Tumongo, Update, db};
+
+/// model operations limited to one tenant of `#[tumongo(tenant_field = "..")]` models.
+///
+/// filters get the tenant injected, saves and deletes are refused for docs of other tenants.
+/// models without a tenant field are passed through unscoped
+#[derive(Debug, Clone)]
+pub struct TenantScope {
+    db: Database,
+    tenant: Bson,
+}
+
+impl TenantScope {
+    pub fn new(db: &Database, tenant: impl Into<Bson>) -> Self {
+        Self {
+            db: db.clone(),
+            tenant: tenant.into(),
+        }
+    }
+
+    /// uses the database `<prefix><tenant>` on the same client, for database per tenant setups
+    pub fn database_per_tenant(mut self, prefix: &str) -> Self {
+        let tenant = match &self.tenant {
+            Bson::String(s) => s.clone(),
+            Bson::ObjectId(oid) => oid.to_hex(),
+            other => other.to_string(),
+        };
+        self.db = self.db.client().database(&format!("{prefix}{tenant}"));
+        self
+    }
+
+    pub fn db(&self) -> &Database {
+        &self.db
+    }
+
+    pub fn tenant(&self) -> &Bson {
+        &self.tenant
+    }
+
+    /// `filter` limited to this tenant, fails if it asks for another one
+    pub fn filter<M: Model>(&self, mut filter: Document) -> Res<Document> {
+        let Some(field) = M::tenant_field() else {
+            return Ok(filter);
+        };
+        match filter.get(field) {
+            Some(val) if val == &self.tenant => {}
+            Some(val) => {
+                return Err(format!(
+                    "[{}] filter asks for {field} = {val}, scope is {}",
+                    M::coll_name(),
+                    self.tenant
+                )
+                .into());
+            }
+            None => {
+                filter.insert(field, self.tenant.clone());
+            }
+        }
+        Ok(filter)
+    }
+
+    pub async fn find<M: Model>(&self, filter: Document, opts: FindOpts) -> Res<Vec<M>> {
+        Tumongo::find(&self.db, self.filter::<M>(filter)?, opts).await
+    }
+
+    pub async fn find_one<M: Model>(&self, filter: Document, opts: FindOpts) -> Res<M> {
+        Tumongo::find_one(&self.db, self.filter::<M>(filter)?, opts).await
+    }
+
+    pub async fn find_by_id<M: Model>(&self, id: &ObjectId) -> Res<M> {
+        self.find_one(doc! {"_id": id}, FindOpts::strict()).await
+    }
+
+    pub async fn count<M: Model>(&self, filter: Document) -> Res<u64> {
+        Tumongo::count::<M>(&self.db, self.filter::<M>(filter)?).await
+    }
+
+    /// fails unless `item` is of this tenant, stored docs are checked too so a save can't move one
+    async fn verify<M: Model>(&self, item: &M) -> Res<()> {
+        let Some(field) = M::tenant_field() else {
+            return Ok(());
+        };
+        let val = item.to_doc().get(field).cloned().unwrap_or(Bson::Null);
+        if val != self.tenant {
+            return Err(format!("[{}] {field} is {val}, scope is {}", M::coll_name(), self.tenant).into());
+        }
+        if let Some(_id) = item.id() {
+            let stored = Tumongo::find_one_doc(&self.db, &M::coll_name(), doc! {"_id": _id}, None).await?;
+            if stored.and_then(|dok| dok.get(field).cloned()).is_some_and(|stored| stored != self.tenant) {
+                return Err(format!("[{}] {_id} belongs to another tenant", M::coll_name()).into());
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn save<M: Model>(&self, item: &mut M) -> Res<()> {
+        self.verify(item).await?;
+        item.save(&self.db).await
+    }
+
+    /// [Tumongo::upsert_by] of `item`, the tenant field is always part of the keys
+    pub async fn upsert_by<M: Model>(&self, keys: &[&str], item: &M) -> Res<M> {
+        self.verify(item).await?;
+        Tumongo::upsert_by(&self.db, keys, item).await
+    }
+
+    /// [Tumongo::find_or_create], the created doc gets this tenant from the filter
+    pub async fn find_or_create<M: Model>(&self, filter: Document, default: impl FnOnce() -> M) -> Res<M> {
+        Tumongo::find_or_create(&self.db, self.filter::<M>(filter)?, default).await
+    }
+
+    /// [Update] limited to this tenant's docs
+    pub fn update<M: Model>(&self, filter: Document) -> Res<Update<'_, M>> {
+        Ok(Update::new(&self.db, self.filter::<M>(filter)?))
+    }
+
+    /// [Aggregate] starting with a `$match` on this tenant, its lookups only join this tenant's docs
+    pub fn aggregate<M: Model>(&self) -> Res<Aggregate<M>> {
+        Ok(Aggregate::new()
+            .scoped(self.tenant.clone())
+            .match_(self.filter::<M>(Document::new())?))
+    }
+
+    /// [Tumongo::history] of a doc of this tenant
+    pub async fn history<M: Model>(&self, id: &ObjectId) -> Res<Vec<HistoryRecord>> {
+        match M::tenant_field() {
+            Some(field) => Tumongo::tenant_history(&self.db, &M::coll_name(), id, field, &self.tenant).await,
+            None => Tumongo::history(&self.db, &M::coll_name(), id).await,
+        }
+    }
+
+    /// [Tumongo::revert_to] of a doc of this tenant, fails if that version belonged to another one
+    pub async fn revert_to<M: Model>(&self, id: &ObjectId, version: i64) -> Res<M> {
+        let coll_name = M::coll_name();
+        let dok = match M::tenant_field() {
+            Some(field) => {
+                let records = Tumongo::tenant_history(&self.db, &coll_name, id, field, &self.tenant).await?;
+                Tumongo::revert_records(&self.db, &coll_name, id, version, records, Some((field, &self.tenant)))
+                    .await?
+            }
+            None => Tumongo::revert_to(&self.db, &coll_name, id, version).await?,
+        };
+        Ok(M::from_doc(dok)?)
+    }
+
+    /// cascades stay within the tenant
+    pub async fn delete<M: Model>(&self, item: &mut M) -> Res<()> {
+        self.verify(item).await?;
+        item.delete(&self.db).await
+    }
+
+    /// [Tumongo::populate] of `item`, related docs of other tenants are left out
+    pub async fn populate<M: Model>(&self, item: &M, coll_names: Option<&[&str]>) -> Res<Document> {
+        self.verify(item).await?;
+        Ok(Tumongo::populate(
+            &self.db,
+            item.to_doc(),
+            &M::coll_name(),
+            coll_names,
+            Tumongo::fk_fields(),
+            Tumongo::ref_fields(),
+        )
+        .await)
+    }
+}
+
+impl Tumongo {
+    /// the tenant field of `coll_name`, from the registry
+    pub fn tenant_field(coll_name: &str) -> Option<&'static str> {
+        COLL_ROUTES
+            .get()
+            .and_then(|routes| routes.get(coll_name))
+            .and_then(|route| route.tenant_field.as_deref())
+    }
+
+    /// fails for tenant models unless `filter` has the tenant field equal to a single value,
+    /// so unscoped reads can't see every tenant's docs. `{tenant_id: {"$ne": null}}` and the like fail too
+    pub fn check_tenant<M: Model>(filter: &Document) -> Res<()> {
+        let Some(field) = M::tenant_field() else {
+            return Ok(());
+        };
+        match filter.get(field) {
+            None => Err(format!("[{}] {field} filter required, use a TenantScope", M::coll_name()).into()),
+            Some(val) if !Self::is_tenant_value(val) => Err(format!(
+                "[{}] {field} filter should be a single tenant, got {val}",
+                M::coll_name()
+            )
+            .into()),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// values that match one tenant on equality, no operators, regexes, arrays or null
+    fn is_tenant_value(val: &Bson) -> bool {
+        !matches!(
+            val,
+            Bson::Null
+                | Bson::Undefined
+                | Bson::Document(_)
+                | Bson::Array(_)
+                | Bson::RegularExpression(_)
+                | Bson::JavaScriptCode(_)
+                | Bson::JavaScriptCodeWithScope(_)
+        )
+    }
+
+    /// tenant value of a `coll_name` doc
+    pub(crate) fn tenant_of(coll_name: &str, dok: &Document) -> Option<Bson> {
+        let field = Self::tenant_field(coll_name)?;
+        dok.get(field).filter(|v| !matches!(v, Bson::Null)).cloned()
+    }
+
+    /// tenant value of the stored `coll_name` doc `id`
+    pub(crate) async fn stored_tenant(
+        db: &Database,
+        coll_name: &str,
+        id: &ObjectId,
+        sess: Option<&mut ClientSession>,
+    ) -> db::error::Result<Option<Bson>> {
+        if Self::tenant_field(coll_name).is_none() {
+            return Ok(None);
+        }
+        let dok = Self::find_one_doc(db, coll_name, doc! {"_id": id}, sess).await?;
+        Ok(dok.and_then(|dok| Self::tenant_of(coll_name, &dok)))
+    }
+
+    /// limits a relation `filter` on `coll_name` to `tenant` if the collection has a tenant field
+    pub(crate) fn tenant_filter(coll_name: &str, tenant: Option<&Bson>, mut filter: Document) -> Document {
+        if let (Some(field), Some(tenant)) = (Self::tenant_field(coll_name), tenant) {
+            filter.insert(field, tenant.clone());
+        }
+        filter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    struct Invoice {
+        company_id: String,
+    }
+
+    #[async_trait]
+    impl Model for Invoice {
+        fn coll_name() -> String {
+            "invoice".into()
+        }
+        fn tenant_field() -> Option<&'static str> {
+            Some("company_id")
+        }
+        fn id(&self) -> Option<ObjectId> {
+            None
+        }
+        fn set_id(&mut self, _id: ObjectId) {}
+        fn to_doc(&self) -> Document {
+            mongodb::bson::to_document(self).unwrap()
+        }
+        fn take_snapshot(&mut self) {}
+        async fn save(&mut self, _db: &Database) -> Res<()> {
+            unimplemented!()
+        }
+        async fn delete(&mut self, _db: &Database) -> Res<()> {
+            unimplemented!()
+        }
+        async fn save_with_session(&mut self, _db: &Database, _sess: &mut ClientSession) -> Res<()> {
+            unimplemented!()
+        }
+        async fn delete_with_session(&mut self, _db: &Database, _sess: &mut ClientSession) -> Res<()> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn check_tenant_wants_one_tenant() {
+        assert!(Tumongo::check_tenant::<Invoice>(&doc! {"company_id": "acme"}).is_ok());
+        assert!(Tumongo::check_tenant::<Invoice>(&doc! {"company_id": "acme", "paid": true}).is_ok());
+        assert!(Tumongo::check_tenant::<Invoice>(&doc! {"paid": true}).is_err());
+        assert!(Tumongo::check_tenant::<Invoice>(&doc! {"company_id": null}).is_err());
+        assert!(Tumongo::check_tenant::<Invoice>(&doc! {"company_id": {"$ne": null}}).is_err());
+        assert!(Tumongo::check_tenant::<Invoice>(&doc! {"company_id": {"$in": ["acme", "globex"]}}).is_err());
+        assert!(Tumongo::check_tenant::<Invoice>(&doc! {"company_id": ["acme"]}).is_err());
+        let regex = mongodb::bson::Regex {
+            pattern: ".*".into(),
+            options: String::new(),
+        };
+        assert!(Tumongo::check_tenant::<Invoice>(&doc! {"company_id": regex}).is_err());
+    }
+
+    #[tokio::test]
+    async fn filter_injects_the_tenant() {
+        let client = mongodb::Client::with_uri_str("mongodb://localhost:27017").await.unwrap();
+        let scope = TenantScope::new(&client.database("test"), "acme");
+        assert_eq!(
+            scope.filter::<Invoice>(doc! {"paid": true}).unwrap(),
+            doc! {"paid": true, "company_id": "acme"}
+        );
+        assert_eq!(
+            scope.filter::<Invoice>(doc! {"company_id": "acme"}).unwrap(),
+            doc! {"company_id": "acme"}
+        );
+        assert!(scope.filter::<Invoice>(doc! {"company_id": "globex"}).is_err());
+        assert!(scope.filter::<Invoice>(doc! {"company_id": {"$ne": null}}).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use async_trait::async_trait;
+use mongodb::{
+    ClientSession, Database,
+    bson::{self, Document, oid::ObjectId},
+};
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{IndexSpec, IndexSync, Res, Tumongo, ValidatorOpts};
+
+/// implemented by `#[derive(TumongoModel)]`, lets the generic helpers work with any model
+#[async_trait]
+pub trait Model: Serialize + DeserializeOwned + Send + Sync + Unpin + Sized {
+    fn coll_name() -> String;
+    /// true for `#[tumongo(soft_delete)]` models
+    fn soft_delete() -> bool {
+        false
+    }
+    /// the `#[tumongo(tenant_field = "..")]`, see [crate::TenantScope]
+    fn tenant_field() -> Option<&'static str> {
+        None
+    }
+    /// true for `#[tumongo(history)]` models
+    fn history() -> bool {
+        false
+    }
+    /// the `#[tumongo(version)]` field
+    fn version_field() -> Option<&'static str> {
+        None
+    }
+    /// the `#[tumongo(unique)]` fields
+    fn unique_fields() -> Vec<&'static str> {
+        vec![]
+    }
+    /// `#[tumongo(unique_if_same = "..")]` fields as (field, same field)
+    fn unique_if_same_fields() -> Vec<(&'static str, &'static str)> {
+        vec![]
+    }
+    /// `#[tumongo(sequence = "..")]` fields as (field, sequence name, sequence_scope field)
+    fn sequence_fields() -> Vec<(&'static str, &'static str, Option<&'static str>)> {
+        vec![]
+    }
+    /// the `#[tumongo(index(..))]` and `#[tumongo(ttl = "..")]` indexes
+    fn indexes() -> Vec<IndexSpec> {
+        vec![]
+    }
+    /// `$jsonSchema` generated from the fields and their validation attributes
+    fn json_schema() -> Document {
+        Document::new()
+    }
+    fn id(&self) -> Option<ObjectId>;
+    fn set_id(&mut self, id: ObjectId);
+    fn to_doc(&self) -> Document;
+    /// stores the current state for dirty tracking, no-op without a snapshot field
+    fn take_snapshot(&mut self);
+    /// ($set, $unset) docs relative to the snapshot, everything is set without a snapshot field
+    fn changes(&self) -> (Document, Document) {
+        (self.to_doc(), Document::new())
+    }
+
+    /// deserializes a raw doc, copying `_id` into the id field
+    fn from_doc(dok: Document) -> bson::de::Result<Self> {
+        let _id = dok.get_object_id("_id").ok();
+        let mut item: Self = bson::from_document(dok)?;
+        if let Some(_id) = _id {
+            item.set_id(_id);
+        }
+        item.take_snapshot();
+        Ok(item)
+    }
+
+    /// the generated `save`
+    async fn save(&mut self, db: &Database) -> Res<()>;
+    /// the generated `delete`, soft for soft delete models
+    async fn delete(&mut self, db: &Database) -> Res<()>;
+    async fn save_with_session(&mut self, db: &Database, sess: &mut ClientSession) -> Res<()>;
+    async fn delete_with_session(&mut self, db: &Database, sess: &mut ClientSession) -> Res<()>;
+
+    /// see [Tumongo::sync_indexes]
+    async fn sync_indexes(db: &Database, drop_undeclared: bool) -> Res<IndexSync> {
+        Tumongo::sync_indexes::<Self>(db, drop_undeclared).await
+    }
+
+    /// see [Tumongo::apply_validator]
+    async fn apply_validator(db: &Database, opts: &ValidatorOpts) -> Res<()> {
+        Tumongo::apply_validator::<Self>(db, opts).await
+    }
+}
+
+/// partial struct of model `M`, implemented by `#[derive(Projection)]`
+pub trait Projection<M: Model>: DeserializeOwned + Send + Sync + Unpin + Sized {
+    /// the `$project` doc
+    fn projection() -> Document;
+
+    /// deserializes a projected doc, `_id` goes into an `id` field if there is one
+    fn from_doc(mut dok: Document) -> bson::de::Result<Self> {
+        if let Some(_id) = dok.remove("_id") {
+            dok.insert("id", _id);
+        }
+        bson::from_document(dok)
+    }
+}
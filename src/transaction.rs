@@ -0,0 +1,158 @@
+use futures_util::lock::{Mutex, MutexGuard};
+use mongodb::{
+    ClientSession, Database,
+    bson::Document,
+    error::{Error, TRANSIENT_TRANSACTION_ERROR, UNKNOWN_TRANSACTION_COMMIT_RESULT},
+    options::{ReadConcern, TransactionOptions, WriteConcern},
+};
+use std::{future::Future, sync::Arc};
+
+use crate::{FindOpts, Model, Res, Tumongo};
+
+/// options for [Tumongo::transaction_with]
+#[derive(Debug, Clone)]
+pub struct TxOpts {
+    pub read_concern: Option<ReadConcern>,
+    pub write_concern: Option<WriteConcern>,
+    /// how many times the whole transaction or its commit is retried on transient errors
+    pub max_retries: u32,
+}
+
+impl Default for TxOpts {
+    fn default() -> Self {
+        Self {
+            read_concern: None,
+            write_concern: None,
+            max_retries: 5,
+        }
+    }
+}
+
+impl TxOpts {
+    fn options(&self) -> TransactionOptions {
+        TransactionOptions::builder()
+            .read_concern(self.read_concern.clone())
+            .write_concern(self.write_concern.clone())
+            .build()
+    }
+}
+
+/// handle to the running transaction, passed to the [Tumongo::transaction] closure.
+///
+/// cheap to clone, model operations through it run inside the transaction
+#[derive(Clone)]
+pub struct Tx {
+    db: Database,
+    sess: Arc<Mutex<ClientSession>>,
+}
+
+impl Tx {
+    pub fn db(&self) -> &Database {
+        &self.db
+    }
+
+    /// the transaction's session, for the `_with_session` methods and raw driver calls.
+    /// don't hold it across other Tx calls
+    pub async fn session(&self) -> MutexGuard<'_, ClientSession> {
+        self.sess.lock().await
+    }
+
+    pub async fn save<M: Model>(&self, item: &mut M) -> Res<()> {
+        let mut sess = self.sess.lock().await;
+        item.save_with_session(&self.db, &mut sess).await
+    }
+
+    pub async fn delete<M: Model>(&self, item: &mut M) -> Res<()> {
+        let mut sess = self.sess.lock().await;
+        item.delete_with_session(&self.db, &mut sess).await
+    }
+
+    pub async fn find<M: Model>(&self, filter: Document, opts: FindOpts) -> Res<Vec<M>> {
+        let mut sess = self.sess.lock().await;
+        Tumongo::find_with_session(&self.db, filter, opts, &mut sess).await
+    }
+
+    pub async fn find_one<M: Model>(&self, filter: Document) -> Res<M> {
+        let mut sess = self.sess.lock().await;
+        Tumongo::find_one_with_session(&self.db, filter, FindOpts::strict(), &mut sess).await
+    }
+}
+
+fn has_label(err: &(dyn std::error::Error + Send + Sync + 'static), label: &str) -> bool {
+    err.downcast_ref::<Error>()
+        .is_some_and(|e| e.contains_label(label))
+}
+
+impl Tumongo {
+    /// runs `f` in a transaction with the default [TxOpts], see [Tumongo::transaction_with]
+    pub async fn transaction<T, F, Fut>(db: &Database, f: F) -> Res<T>
+    where
+        F: Fn(Tx) -> Fut,
+        Fut: Future<Output = Res<T>>,
+    {
+        Self::transaction_with(db, TxOpts::default(), f).await
+    }
+
+    /// runs `f` in a transaction and commits if it returns Ok, aborts otherwise.
+    ///
+    /// `f` is run again on TransientTransactionError and the commit is retried on
+    /// UnknownTransactionCommitResult, up to `opts.max_retries` times each
+    pub async fn transaction_with<T, F, Fut>(db: &Database, opts: TxOpts, f: F) -> Res<T>
+    where
+        F: Fn(Tx) -> Fut,
+        Fut: Future<Output = Res<T>>,
+    {
+        let sess = db.client().start_session().await?;
+        let tx = Tx {
+            db: db.clone(),
+            sess: Arc::new(Mutex::new(sess)),
+        };
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            tx.sess
+                .lock()
+                .await
+                .start_transaction()
+                .with_options(opts.options())
+                .await?;
+
+            let val = match f(tx.clone()).await {
+                Ok(val) => val,
+                Err(err) => {
+                    let mut sess = tx.sess.lock().await;
+                    if let Err(abort_err) = sess.abort_transaction().await {
+                        eprintln!("[transaction] abort failed: {abort_err}");
+                    }
+                    if attempt <= opts.max_retries && has_label(err.as_ref(), TRANSIENT_TRANSACTION_ERROR) {
+                        println!("\n[transaction] transient error, retrying ({attempt})...");
+                        continue;
+                    }
+                    return Err(err);
+                }
+            };
+
+            let mut commit_attempt = 0;
+            let retry_tx = loop {
+                commit_attempt += 1;
+                let res = tx.sess.lock().await.commit_transaction().await;
+                match res {
+                    Ok(()) => return Ok(val),
+                    Err(err)
+                        if commit_attempt <= opts.max_retries
+                            && err.contains_label(UNKNOWN_TRANSACTION_COMMIT_RESULT) =>
+                    {
+                        println!("\n[transaction] unknown commit result, retrying commit...");
+                    }
+                    Err(err) if err.contains_label(TRANSIENT_TRANSACTION_ERROR) => break err,
+                    Err(err) => return Err(err.into()),
+                }
+            };
+            if attempt > opts.max_retries {
+                return Err(retry_tx.into());
+            }
+            println!("\n[transaction] transient commit error, retrying ({attempt})...");
+        }
+    }
+}
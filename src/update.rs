@@ -0,0 +1,112 @@
+use mongodb::{
+    Database,
+    bson::{self, Bson, Document},
+    results::UpdateResult,
+};
+use serde::Serialize;
+use std::marker::PhantomData;
+
+use crate::{Deleted, Field, Model, Res, Tumongo};
+
+fn to_bson<V: Serialize>(v: &V) -> Bson {
+    bson::to_bson(v).expect("Failed to convert update value to bson")
+}
+
+/// ops[op][field] = v
+fn insert_op(ops: &mut Document, op: &str, field: &str, v: Bson) {
+    let fields = ops
+        .entry(op.to_owned())
+        .or_insert_with(|| Document::new().into());
+    if let Bson::Document(fields) = fields {
+        fields.insert(field, v);
+    }
+}
+
+/// typed update operators for model `M`, see the generated `update()`/`update_fields()`.
+///
+/// updated_at is set unless the update sets it itself, the version field is bumped.
+/// doesn't go through save, so no unique checks or history.
+/// tenant models need the tenant field in the filter, see [crate::TenantScope::update]
+pub struct Update<'a, M> {
+    db: &'a Database,
+    filter: Document,
+    ops: Document,
+    _model: PhantomData<fn() -> M>,
+}
+
+impl<'a, M: Model> Update<'a, M> {
+    pub fn new(db: &'a Database, filter: Document) -> Self {
+        Self {
+            db,
+            filter,
+            ops: Document::new(),
+            _model: PhantomData,
+        }
+    }
+
+    fn op(mut self, op: &str, field: &str, v: Bson) -> Self {
+        insert_op(&mut self.ops, op, field, v);
+        self
+    }
+
+    pub fn set<T: Serialize>(self, f: Field<M, T>, v: impl Into<T>) -> Self {
+        self.op("$set", f.name(), to_bson(&v.into()))
+    }
+    pub fn unset<T>(self, f: Field<M, T>) -> Self {
+        self.op("$unset", f.name(), Bson::String(String::new()))
+    }
+    pub fn inc<T: Serialize>(self, f: Field<M, T>, by: impl Into<T>) -> Self {
+        self.op("$inc", f.name(), to_bson(&by.into()))
+    }
+    pub fn push<E: Serialize>(self, f: Field<M, Vec<E>>, v: impl Into<E>) -> Self {
+        self.op("$push", f.name(), to_bson(&v.into()))
+    }
+    pub fn add_to_set<E: Serialize>(self, f: Field<M, Vec<E>>, v: impl Into<E>) -> Self {
+        self.op("$addToSet", f.name(), to_bson(&v.into()))
+    }
+    /// removes every element equal to `v`
+    pub fn pull<E: Serialize>(self, f: Field<M, Vec<E>>, v: impl Into<E>) -> Self {
+        self.op("$pull", f.name(), to_bson(&v.into()))
+    }
+
+    /// the update doc as sent, including updated_at and the version bump
+    pub fn to_doc(&self) -> Document {
+        let mut ops = self.ops.clone();
+        let sets_updated_at = ["$set", "$unset"]
+            .iter()
+            .any(|op| ops.get_document(op).is_ok_and(|d| d.contains_key("updated_at")));
+        if !sets_updated_at {
+            insert_op(&mut ops, "$set", "updated_at", bson::DateTime::now().into());
+        }
+        // saves of instances loaded before this update get a Conflict
+        if let Some(field) = M::version_field() {
+            let touched = ops.values().any(|fields| matches!(fields, Bson::Document(d) if d.contains_key(field)));
+            if !touched {
+                insert_op(&mut ops, "$inc", field, Bson::Int32(1));
+            }
+        }
+        ops
+    }
+
+    fn filter(&self) -> Document {
+        Tumongo::scope_filter::<M>(self.filter.clone(), Deleted::Exclude)
+    }
+
+    /// updates the first matching doc
+    pub async fn one(self) -> Res<UpdateResult> {
+        Tumongo::check_tenant::<M>(&self.filter)?;
+        let res = Tumongo::coll::<Document>(self.db, &M::coll_name())?
+            .update_one(self.filter(), self.to_doc())
+            .await?;
+        Ok(res)
+    }
+
+    /// updates all matching docs
+    pub async fn many(self) -> Res<UpdateResult> {
+        Tumongo::check_tenant::<M>(&self.filter)?;
+        let res = Tumongo::coll::<Document>(self.db, &M::coll_name())?
+            .update_many(self.filter(), self.to_doc())
+            .await?;
+        Ok(res)
+    }
+}
@@ -0,0 +1,133 @@
+use mongodb::{
+    Database,
+    bson::{self, Bson, Document, doc},
+    options::ReturnDocument,
+};
+
+use crate::{DELETED_AT, Deleted, Model, Res, Tumongo};
+
+impl Tumongo {
+    /// updates the doc whose `keys` fields equal `item`'s or inserts `item`, returns the stored doc.
+    ///
+    /// created_at is only written on insert, updated_at always. the version field, `deleted_at` and
+    /// sequence fields keep their stored values on updates, the version is bumped. soft deleted docs
+    /// don't match. unique checks are left to the db. tenant models also match on item's tenant field
+    pub async fn upsert_by<M: Model>(db: &Database, keys: &[&str], item: &M) -> Res<M> {
+        if keys.is_empty() {
+            return Err(format!("No upsert keys for {}", M::coll_name()).into());
+        }
+        let mut set = item.to_doc();
+        set.remove("_id");
+        set.remove("created_at");
+
+        let mut filter = Document::new();
+        for key in keys {
+            let key = if *key == "id" { "_id" } else { key };
+            let val = match key {
+                "_id" => item.id().map(Into::into),
+                _ => set.get(key).cloned(),
+            };
+            match val {
+                Some(val) => filter.insert(key, val),
+                None => return Err(format!("No upsert key {key} in {}", M::coll_name()).into()),
+            };
+        }
+        // only ever matches a doc of item's own tenant
+        if let Some(field) = M::tenant_field() {
+            filter.insert(field, set.get(field).cloned().unwrap_or(Bson::Null));
+        }
+        let filter = Self::scope_filter::<M>(filter, Deleted::Exclude);
+
+        // only written on insert
+        let mut on_insert = Document::new();
+        let mut update = Document::new();
+        if let Some(field) = M::version_field() {
+            on_insert.insert(field, set.remove(field).unwrap_or(Bson::Int64(0)));
+            update.insert("$inc", doc! {field: 1});
+        }
+        if M::soft_delete() {
+            set.remove(DELETED_AT);
+            on_insert.insert(DELETED_AT, Bson::Null);
+        }
+        let sequences: Vec<_> = M::sequence_fields()
+            .into_iter()
+            .filter_map(|(field, name, scope)| Some((field, name, scope, set.remove(field)?)))
+            .collect();
+
+        let now = bson::DateTime::now();
+        set.insert("updated_at", now);
+        update.insert("$set", set.clone());
+        let coll = Self::coll::<Document>(db, &M::coll_name())?;
+        // existing docs first, so sequence numbers are only drawn for inserts
+        if let Some(dok) = coll
+            .find_one_and_update(filter.clone(), update)
+            .return_document(ReturnDocument::After)
+            .await?
+        {
+            return Ok(M::from_doc(dok)?);
+        }
+
+        on_insert.insert("created_at", now);
+        if !sequences.is_empty() {
+            let model_db = Self::route_db(db, &M::coll_name())?;
+            let mut sess = model_db.client().start_session().await?;
+            for (field, name, scope, val) in sequences {
+                let scope = scope.and_then(|scope| set.get(scope).cloned());
+                // like save, a 0 gets the next number
+                let val = match val {
+                    Bson::Int32(0) => Bson::Int32(Self::next_sequence(&model_db, &mut sess, name, scope).await?.try_into()?),
+                    Bson::Int64(0) => Bson::Int64(Self::next_sequence(&model_db, &mut sess, name, scope).await?),
+                    val => val,
+                };
+                on_insert.insert(field, val);
+            }
+        }
+        let dok = coll
+            .find_one_and_update(
+                filter,
+                doc! {
+                    "$set": set,
+                    "$setOnInsert": on_insert,
+                },
+            )
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .await?
+            .ok_or("upsert returned no doc")?;
+        Ok(M::from_doc(dok)?)
+    }
+
+    /// first doc matching `filter`, inserting `default()` (plus the filter's equality fields) if there's none.
+    /// operator conditions like `{age: {"$gte": 18}}` keep `default()`'s value
+    ///
+    /// tenant models need the tenant field in the filter, see [crate::TenantScope::find_or_create]
+    pub async fn find_or_create<M: Model>(
+        db: &Database,
+        filter: Document,
+        default: impl FnOnce() -> M,
+    ) -> Res<M> {
+        Self::check_tenant::<M>(&filter)?;
+        let mut insert = default().to_doc();
+        insert.remove("_id");
+        let now = bson::DateTime::now();
+        insert.insert("created_at", now);
+        insert.insert("updated_at", now);
+        // equality fields in the filter come from the filter on insert, the others from `default()`
+        for (k, v) in filter.iter() {
+            let is_op = matches!(v, Bson::Document(d) if d.keys().next().is_some_and(|k| k.starts_with('$')));
+            if !is_op {
+                insert.remove(k);
+            }
+        }
+        let dok = Self::coll::<Document>(db, &M::coll_name())?
+            .find_one_and_update(
+                Self::scope_filter::<M>(filter, Deleted::Exclude),
+                doc! {"$setOnInsert": insert},
+            )
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .await?
+            .ok_or("find_or_create returned no doc")?;
+        Ok(M::from_doc(dok)?)
+    }
+}
@@ -0,0 +1,58 @@
+use mongodb::{
+    Database,
+    bson::{self, Document, doc},
+    options::{ValidationAction, ValidationLevel},
+};
+
+use crate::{Model, Res, Tumongo};
+
+/// how the server applies a [Tumongo::apply_validator] validator
+#[derive(Debug, Clone)]
+pub struct ValidatorOpts {
+    pub level: ValidationLevel,
+    pub action: ValidationAction,
+}
+
+impl Default for ValidatorOpts {
+    fn default() -> Self {
+        Self {
+            level: ValidationLevel::Strict,
+            action: ValidationAction::Error,
+        }
+    }
+}
+
+impl Tumongo {
+    /// parses a generated `$jsonSchema`
+    pub fn parse_schema(json: &str) -> Document {
+        let val: serde_json::Value = serde_json::from_str(json).expect("Invalid json schema");
+        bson::to_document(&val).expect("Invalid json schema")
+    }
+
+    /// `{"$jsonSchema": ..}` of `M`
+    pub fn validator<M: Model>() -> Document {
+        doc! {"$jsonSchema": M::json_schema()}
+    }
+
+    /// sets the validator of `M`'s collection, with createCollection if it doesn't exist yet
+    /// and collMod otherwise
+    pub async fn apply_validator<M: Model>(db: &Database, opts: &ValidatorOpts) -> Res<()> {
+        let coll_name = M::coll_name();
+        let db = Self::route_db(db, &coll_name)?;
+        let exists = !db
+            .list_collection_names()
+            .filter(doc! {"name": coll_name.as_str()})
+            .await?
+            .is_empty();
+        let cmd = if exists { "collMod" } else { "create" };
+        println!("\n[{coll_name}] Applying validator ({cmd})...");
+        db.run_command(doc! {
+            cmd: coll_name.as_str(),
+            "validator": Self::validator::<M>(),
+            "validationLevel": bson::to_bson(&opts.level)?,
+            "validationAction": bson::to_bson(&opts.action)?,
+        })
+        .await?;
+        Ok(())
+    }
+}
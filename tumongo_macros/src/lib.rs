@@ -1,3 +1,5 @@
+mod projection;
+mod schema;
 mod tumongo_model;
 mod types;
 
@@ -6,19 +8,31 @@ use proc_macro::TokenStream;
 use quote::quote;
 use std::{collections::HashMap, sync::Mutex};
 use syn::{DeriveInput, parse_macro_input};
-use types::FkFieldMap;
+use types::{CollRoute, FkFieldMap};
 
 static FK_FIELDS: Lazy<Mutex<FkFieldMap>> = Lazy::new(|| Mutex::new(HashMap::new()));
 static REF_FIELDS: Lazy<Mutex<FkFieldMap>> = Lazy::new(|| Mutex::new(HashMap::new()));
 /// key = coll_name
 static UNIQUE_FIELDS: Lazy<Mutex<HashMap<String, Vec<String>>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
+/// coll_names of soft delete models
+static SOFT_DELETE_COLLS: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(vec![]));
+/// key = coll_name of models with a conn or db
+static COLL_ROUTES: Lazy<Mutex<HashMap<String, CollRoute>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
 #[proc_macro_derive(TumongoModel, attributes(tumongo))]
 pub fn tumongo_model_macro(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     tumongo_model::main(input)
 }
 
+/// partial view of a TumongoModel: `#[tumongo(model = "Person")]`
+#[proc_macro_derive(Projection, attributes(tumongo))]
+pub fn projection_macro(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    projection::main(input)
+}
+
 #[proc_macro]
 pub fn register(_inp: TokenStream) -> TokenStream {
     let fk_fields = FK_FIELDS.lock().unwrap().clone();
@@ -29,10 +43,32 @@ pub fn register(_inp: TokenStream) -> TokenStream {
 
     let unique_fields = UNIQUE_FIELDS.lock().unwrap().clone();
     let unique_fields_str = serde_json::to_string(&unique_fields).unwrap();
+
+    let soft_delete_colls = SOFT_DELETE_COLLS.lock().unwrap().clone();
+    let soft_delete_colls_str = serde_json::to_string(&soft_delete_colls).unwrap();
+
+    let coll_routes = COLL_ROUTES.lock().unwrap().clone();
+    let coll_routes_str = serde_json::to_string(&coll_routes).unwrap();
+
+    // a soft delete would have to destroy these children for good
+    for parent in &soft_delete_colls {
+        for child in fk_fields.get(parent).into_iter().flatten() {
+            if child.on_delete.as_deref() == Some("cascade") && !soft_delete_colls.contains(&child.coll) {
+                let msg = format!(
+                    "{}.{} cascades from soft delete model {parent}, make {} soft delete too or use on_delete = \"null\"",
+                    child.coll, child.field_name, child.coll
+                );
+                return quote! { compile_error!(#msg); }.into();
+            }
+        }
+    }
     quote! {
-        FK_FIELDS.set(serde_json::from_str(#fk_fields_str).unwrap()).unwrap();
-        REF_FIELDS.set(serde_json::from_str(#ref_fields_str).unwrap()).unwrap();
-        UNIQUE_FIELDS.set(serde_json::from_str(#unique_fields_str).unwrap()).unwrap();
+        // already set by an earlier call is fine
+        let _ = ::tumongo::FK_FIELDS.set(::tumongo::serde_json::from_str(#fk_fields_str).unwrap());
+        let _ = ::tumongo::REF_FIELDS.set(::tumongo::serde_json::from_str(#ref_fields_str).unwrap());
+        let _ = ::tumongo::UNIQUE_FIELDS.set(::tumongo::serde_json::from_str(#unique_fields_str).unwrap());
+        let _ = ::tumongo::SOFT_DELETE_COLLS.set(::tumongo::serde_json::from_str(#soft_delete_colls_str).unwrap());
+        let _ = ::tumongo::COLL_ROUTES.set(::tumongo::serde_json::from_str(#coll_routes_str).unwrap());
     }
     .into()
 }
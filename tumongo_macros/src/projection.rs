@@ -0,0 +1,78 @@
+use darling::{FromDeriveInput, FromField};
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::DeriveInput;
+
+use crate::{schema, tumongo_model::snake_case};
+
+#[derive(Debug, FromDeriveInput)]
+#[darling(attributes(tumongo), supports(struct_named), forward_attrs(serde))]
+struct ProjectionOpts {
+    ident: syn::Ident,
+    attrs: Vec<syn::Attribute>,
+    /// the TumongoModel this is a projection of
+    model: syn::Path,
+    data: darling::ast::Data<(), ProjectionField>,
+}
+
+#[derive(Debug, FromField)]
+#[darling(attributes(tumongo), forward_attrs(serde))]
+struct ProjectionField {
+    ident: Option<syn::Ident>,
+    ty: syn::Type,
+    attrs: Vec<syn::Attribute>,
+}
+
+pub fn main(input: DeriveInput) -> TokenStream {
+    let opts = match ProjectionOpts::from_derive_input(&input) {
+        Ok(o) => o,
+        Err(e) => {
+            return e.write_errors().into();
+        }
+    };
+    let struct_name = &opts.ident;
+    let model = &opts.model;
+
+    // the model's field paths live next to it in <model>_fields
+    let mut fields_mod = model.clone();
+    let last = fields_mod.segments.last_mut().expect("empty model path");
+    last.ident = format_ident!("{}_fields", snake_case(&last.ident.to_string()));
+
+    let fields = opts.data.as_ref().take_struct().unwrap().fields;
+    let field_names: Vec<_> = fields.iter().map(|f| f.ident.clone().unwrap()).collect();
+    let field_tys: Vec<_> = fields.iter().map(|f| f.ty.clone()).collect();
+    // the keys this struct reads, the model's stored paths can differ through serde renames
+    let rename_all = schema::serde_rename_all(&opts.attrs);
+    let field_keys: Vec<String> = fields
+        .iter()
+        .map(|f| match f.ident.as_ref().unwrap().to_string() {
+            name if name == "id" => "_id".to_string(),
+            name => schema::serde_field(&f.attrs).stored_name(&name, rename_all.as_deref()),
+        })
+        .collect();
+
+    quote! {
+        // fails to compile if a field isn't on the model or has a different type
+        const _: () = {
+            #(
+                let _: tumongo::Field<#model, #field_tys> = #fields_mod::#field_names;
+            )*
+        };
+
+        impl tumongo::Projection<#model> for #struct_name {
+            fn projection() -> tumongo::db::bson::Document {
+                let mut dok = tumongo::db::bson::Document::new();
+                #(
+                    let path = #fields_mod::#field_names.name();
+                    if path == #field_keys {
+                        dok.insert(path, 1);
+                    } else {
+                        dok.insert(#field_keys, format!("${path}"));
+                    }
+                )*
+                dok
+            }
+        }
+    }
+    .into()
+}
@@ -0,0 +1,295 @@
+use serde_json::{Map, Value, json};
+
+/// `$jsonSchema` of a rust type and whether it's an Option.
+/// types it doesn't know (custom structs/enums) are left unconstrained
+pub(crate) fn type_schema(ty: &syn::Type) -> (Map<String, Value>, bool) {
+    let mut schema = Map::new();
+    let syn::Type::Path(tp) = ty else {
+        return (schema, false);
+    };
+    let Some(seg) = tp.path.segments.last() else {
+        return (schema, false);
+    };
+    let inner = match &seg.arguments {
+        syn::PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+            syn::GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        }),
+        _ => None,
+    };
+
+    let bson_type = match seg.ident.to_string().as_str() {
+        "Option" => {
+            let Some(inner) = inner else {
+                return (schema, true);
+            };
+            let (mut schema, _) = type_schema(inner);
+            if let Some(bson_type) = schema.remove("bsonType") {
+                let mut types = match bson_type {
+                    Value::Array(types) => types,
+                    other => vec![other],
+                };
+                types.push(json!("null"));
+                schema.insert("bsonType".into(), Value::Array(types));
+            }
+            return (schema, true);
+        }
+        "String" | "str" | "char" => json!("string"),
+        "bool" => json!("bool"),
+        // the shell and other drivers don't always pick the same int width
+        "i8" | "i16" | "i32" | "i64" | "isize" | "u8" | "u16" | "u32" | "u64" | "usize" => {
+            json!(["int", "long"])
+        }
+        "f32" | "f64" => json!("number"),
+        "ObjectId" => json!("objectId"),
+        "DateTime" if is_bson_datetime(tp) => json!("date"),
+        "Vec" | "VecDeque" | "HashSet" | "BTreeSet" => {
+            if let Some(inner) = inner {
+                let (items, _) = type_schema(inner);
+                if !items.is_empty() {
+                    schema.insert("items".into(), Value::Object(items));
+                }
+            }
+            json!("array")
+        }
+        "HashMap" | "BTreeMap" | "Document" => json!("object"),
+        _ => return (schema, false),
+    };
+    schema.insert("bsonType".into(), bson_type);
+    (schema, false)
+}
+
+/// tumongo::DateTime and bson::DateTime are stored as dates, chrono's `DateTime<Tz>` isn't.
+/// a bare `DateTime` is taken as one of ours unless it has a type argument
+fn is_bson_datetime(tp: &syn::TypePath) -> bool {
+    let segs: Vec<_> = tp.path.segments.iter().collect();
+    match segs.as_slice() {
+        [seg] => seg.arguments.is_none(),
+        [.., parent, _] => parent.ident == "tumongo" || parent.ident == "bson",
+        [] => false,
+    }
+}
+
+/// how serde stores a field, from its `#[serde(..)]` attributes
+#[derive(Debug, Default)]
+pub(crate) struct SerdeField {
+    pub rename: Option<String>,
+    /// skip, skip_serializing or flatten, no property of its own
+    pub skip: bool,
+    /// skip_serializing_if, may be missing from the doc
+    pub maybe_missing: bool,
+}
+
+pub(crate) fn serde_field(attrs: &[syn::Attribute]) -> SerdeField {
+    let mut field = SerdeField::default();
+    for attr in attrs.iter().filter(|a| a.path().is_ident("serde")) {
+        let res = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                if meta.input.peek(syn::token::Paren) {
+                    // rename(serialize = "..", deserialize = ".."), docs are stored by the first
+                    meta.parse_nested_meta(|inner| {
+                        let val: syn::LitStr = inner.value()?.parse()?;
+                        if inner.path.is_ident("serialize") {
+                            field.rename = Some(val.value());
+                        }
+                        Ok(())
+                    })?;
+                } else {
+                    let val: syn::LitStr = meta.value()?.parse()?;
+                    field.rename = Some(val.value());
+                }
+            } else if ["skip", "skip_serializing", "flatten"].iter().any(|k| meta.path.is_ident(k)) {
+                field.skip = true;
+            } else if meta.path.is_ident("skip_serializing_if") {
+                field.maybe_missing = true;
+                meta.value()?.parse::<syn::Expr>()?;
+            } else if meta.input.peek(syn::Token![=]) {
+                meta.value()?.parse::<syn::Expr>()?;
+            } else if meta.input.peek(syn::token::Paren) {
+                meta.input.parse::<proc_macro2::Group>()?;
+            }
+            Ok(())
+        });
+        if let Err(err) = res {
+            panic!("Invalid serde attribute: {err}");
+        }
+    }
+    field
+}
+
+/// the container's `#[serde(rename_all = "..")]`, the serialize one of `rename_all(..)`
+pub(crate) fn serde_rename_all(attrs: &[syn::Attribute]) -> Option<String> {
+    let mut rename_all = None;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("serde")) {
+        let res = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename_all") {
+                if meta.input.peek(syn::token::Paren) {
+                    meta.parse_nested_meta(|inner| {
+                        let val: syn::LitStr = inner.value()?.parse()?;
+                        if inner.path.is_ident("serialize") {
+                            rename_all = Some(val.value());
+                        }
+                        Ok(())
+                    })?;
+                } else {
+                    let val: syn::LitStr = meta.value()?.parse()?;
+                    rename_all = Some(val.value());
+                }
+            } else if meta.input.peek(syn::Token![=]) {
+                meta.value()?.parse::<syn::Expr>()?;
+            } else if meta.input.peek(syn::token::Paren) {
+                meta.input.parse::<proc_macro2::Group>()?;
+            }
+            Ok(())
+        });
+        if let Err(err) = res {
+            panic!("Invalid serde attribute: {err}");
+        }
+    }
+    rename_all
+}
+
+/// snake_case field `name` as serde's `rename_all` rule stores it
+pub(crate) fn rename_case(name: &str, rule: &str) -> String {
+    let pascal = || {
+        name.split('_')
+            .map(|word| {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(c) => c.to_uppercase().chain(chars).collect(),
+                    None => String::new(),
+                }
+            })
+            .collect::<String>()
+    };
+    match rule {
+        "lowercase" | "snake_case" => name.to_string(),
+        "UPPERCASE" | "SCREAMING_SNAKE_CASE" => name.to_uppercase(),
+        "PascalCase" => pascal(),
+        "camelCase" => {
+            let pascal = pascal();
+            let mut chars = pascal.chars();
+            match chars.next() {
+                Some(c) => c.to_lowercase().chain(chars).collect(),
+                None => pascal,
+            }
+        }
+        "kebab-case" => name.replace('_', "-"),
+        "SCREAMING-KEBAB-CASE" => name.to_uppercase().replace('_', "-"),
+        _ => panic!("Unknown serde rename_all rule {rule:?}"),
+    }
+}
+
+impl SerdeField {
+    /// key of field `name` in the doc, `rename` over the container's `rename_all`
+    pub(crate) fn stored_name(&self, name: &str, rename_all: Option<&str>) -> String {
+        match (&self.rename, rename_all) {
+            (Some(rename), _) => rename.clone(),
+            (None, Some(rule)) => rename_case(name, rule),
+            (None, None) => name.to_string(),
+        }
+    }
+}
+
+pub(crate) fn lit_value(lit: &syn::Lit) -> Value {
+    match lit {
+        syn::Lit::Str(s) => json!(s.value()),
+        syn::Lit::Int(i) => json!(i.base10_parse::<i64>().expect("Invalid int")),
+        syn::Lit::Float(f) => json!(f.base10_parse::<f64>().expect("Invalid float")),
+        syn::Lit::Bool(b) => json!(b.value),
+        _ => panic!("Unsupported literal in tumongo attribute"),
+    }
+}
+
+pub(crate) fn is_array(schema: &Map<String, Value>) -> bool {
+    match schema.get("bsonType") {
+        Some(Value::String(t)) => t == "array",
+        Some(Value::Array(types)) => types.iter().any(|t| t == "array"),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse_quote;
+
+    use super::*;
+
+    fn schema(ty: syn::Type) -> (Value, bool) {
+        let (schema, optional) = type_schema(&ty);
+        (Value::Object(schema), optional)
+    }
+
+    #[test]
+    fn scalars() {
+        assert_eq!(schema(parse_quote!(String)), (json!({"bsonType": "string"}), false));
+        assert_eq!(schema(parse_quote!(i64)), (json!({"bsonType": ["int", "long"]}), false));
+        assert_eq!(schema(parse_quote!(f64)), (json!({"bsonType": "number"}), false));
+        assert_eq!(schema(parse_quote!(ObjectId)), (json!({"bsonType": "objectId"}), false));
+    }
+
+    #[test]
+    fn options_allow_null() {
+        assert_eq!(schema(parse_quote!(Option<bool>)), (json!({"bsonType": ["bool", "null"]}), true));
+        assert_eq!(
+            schema(parse_quote!(Option<u32>)),
+            (json!({"bsonType": ["int", "long", "null"]}), true)
+        );
+        assert_eq!(schema(parse_quote!(Option<MyEnum>)), (json!({}), true));
+    }
+
+    #[test]
+    fn arrays_describe_items() {
+        assert_eq!(
+            schema(parse_quote!(Vec<String>)),
+            (json!({"bsonType": "array", "items": {"bsonType": "string"}}), false)
+        );
+        assert_eq!(schema(parse_quote!(Vec<MyStruct>)), (json!({"bsonType": "array"}), false));
+    }
+
+    #[test]
+    fn only_bson_datetimes_are_dates() {
+        let date = (json!({"bsonType": "date"}), false);
+        assert_eq!(schema(parse_quote!(DateTime)), date);
+        assert_eq!(schema(parse_quote!(tumongo::DateTime)), date);
+        assert_eq!(schema(parse_quote!(mongodb::bson::DateTime)), date);
+        assert_eq!(schema(parse_quote!(chrono::DateTime<Utc>)), (json!({}), false));
+        assert_eq!(schema(parse_quote!(DateTime<Utc>)), (json!({}), false));
+    }
+
+    #[test]
+    fn serde_attrs() {
+        let attrs: Vec<syn::Attribute> = vec![
+            parse_quote!(#[serde(default, rename = "nick")]),
+            parse_quote!(#[serde(skip_serializing_if = "String::is_empty", with = "x")]),
+        ];
+        let field = serde_field(&attrs);
+        assert_eq!(field.rename.as_deref(), Some("nick"));
+        assert!(field.maybe_missing && !field.skip);
+
+        let attrs: Vec<syn::Attribute> = vec![parse_quote!(#[serde(rename(serialize = "a", deserialize = "b"))])];
+        assert_eq!(serde_field(&attrs).rename.as_deref(), Some("a"));
+        assert!(serde_field(&[parse_quote!(#[serde(skip)])]).skip);
+        assert!(serde_field(&[parse_quote!(#[serde(flatten)])]).skip);
+    }
+
+    #[test]
+    fn rename_all() {
+        let attrs: Vec<syn::Attribute> = vec![parse_quote!(#[serde(deny_unknown_fields, rename_all = "camelCase")])];
+        assert_eq!(serde_rename_all(&attrs).as_deref(), Some("camelCase"));
+        let attrs: Vec<syn::Attribute> = vec![parse_quote!(#[serde(rename_all(serialize = "kebab-case"))])];
+        assert_eq!(serde_rename_all(&attrs).as_deref(), Some("kebab-case"));
+        assert_eq!(serde_rename_all(&[parse_quote!(#[serde(default)])]), None);
+
+        assert_eq!(rename_case("company_id", "camelCase"), "companyId");
+        assert_eq!(rename_case("company_id", "PascalCase"), "CompanyId");
+        assert_eq!(rename_case("company_id", "SCREAMING_SNAKE_CASE"), "COMPANY_ID");
+        assert_eq!(rename_case("company_id", "SCREAMING-KEBAB-CASE"), "COMPANY-ID");
+        assert_eq!(rename_case("name", "camelCase"), "name");
+
+        let field = serde_field(&[parse_quote!(#[serde(rename = "nick")])]);
+        assert_eq!(field.stored_name("alias", Some("camelCase")), "nick");
+        assert_eq!(SerdeField::default().stored_name("first_name", Some("camelCase")), "firstName");
+        assert_eq!(SerdeField::default().stored_name("first_name", None), "first_name");
+    }
+}
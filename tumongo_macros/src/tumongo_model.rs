@@ -1,27 +1,76 @@
-use darling::{FromDeriveInput, FromField};
+use darling::{FromDeriveInput, FromField, FromMeta};
 use proc_macro::TokenStream;
 use quote::{format_ident, quote, quote_spanned};
 use strum::VariantNames;
 use syn::DeriveInput;
 
 use crate::{
-    types::{FkField, OnDelete}, FK_FIELDS, REF_FIELDS
+    schema,
+    types::{CollRoute, FkField, IndexSpec, OnDelete}, COLL_ROUTES, FK_FIELDS, REF_FIELDS, SOFT_DELETE_COLLS, UNIQUE_FIELDS
 };
 // use tumongo::FkField;
 
 #[derive(Debug, FromDeriveInput)]
-#[darling(attributes(tumongo), supports(struct_named))]
+#[darling(attributes(tumongo), supports(struct_named), forward_attrs(serde))]
 struct StructOpts {
     coll_name: String,
+    vis: syn::Visibility,
+    attrs: Vec<syn::Attribute>,
+    /// delete only sets deleted_at, see Model::purge for a hard delete
+    #[darling(default)]
+    soft_delete: bool,
+    /// record every save/delete in <coll_name>_history
+    #[darling(default)]
+    history: bool,
+    /// connection name in TumongoConfig, the passed db's connection if None
+    #[darling(default)]
+    conn: Option<String>,
+    /// database name on that connection, the passed db if None
+    #[darling(default)]
+    db: Option<String>,
+    /// primary, primary_preferred, secondary, secondary_preferred or nearest
+    #[darling(default)]
+    read_preference: Option<String>,
+    /// local, available, majority, linearizable or snapshot
+    #[darling(default)]
+    read_concern: Option<String>,
+    /// majority, a node count or a tag set name
+    #[darling(default)]
+    write_concern: Option<String>,
+    /// field holding the tenant, finds without it fail and TenantScope injects it
+    #[darling(default)]
+    tenant_field: Option<String>,
+    /// `index(fields = ["company_id", "-created_at"], unique, sparse, kind = "text", partial = "{..}", name = "..")`
+    #[darling(default, multiple, rename = "index")]
+    indexes: Vec<IndexOpts>,
     ident: syn::Ident,
     data: darling::ast::Data<(), FieldOpts>,
 }
 
+#[derive(Debug, FromMeta)]
+struct IndexOpts {
+    /// paths, `-` prefix for descending
+    fields: syn::ExprArray,
+    #[darling(default)]
+    name: Option<String>,
+    #[darling(default)]
+    unique: bool,
+    #[darling(default)]
+    sparse: bool,
+    /// text or 2dsphere, all fields get it
+    #[darling(default)]
+    kind: Option<String>,
+    /// partialFilterExpression as json
+    #[darling(default)]
+    partial: Option<String>,
+}
+
 #[derive(Debug, FromField)]
-#[darling(attributes(tumongo))]
+#[darling(attributes(tumongo), forward_attrs(serde))]
 struct FieldOpts {
     ident: Option<syn::Ident>,
     ty: syn::Type,
+    attrs: Vec<syn::Attribute>,
     #[darling(default)]
     unique: bool,
     /// unique if same same field = field.value
@@ -38,6 +87,57 @@ struct FieldOpts {
     coll: Option<String>,
     #[darling(default)]
     on_delete: Option<String>,
+    /// holds the last loaded/saved state (tumongo::Snapshot) for dirty tracking
+    #[darling(default)]
+    snapshot: bool,
+    /// optimistic concurrency counter, bumped on every save
+    #[darling(default)]
+    version: bool,
+    /// name of the tumongo_counters sequence filling this field on insert
+    #[darling(default)]
+    sequence: Option<String>,
+    /// field whose value gets its own sequence, e.g company_id
+    #[darling(default)]
+    sequence_scope: Option<String>,
+    /// expire docs this long after the field's date, e.g 30d, 12h, 90m, 3600s
+    #[darling(default)]
+    ttl: Option<String>,
+    /// `$jsonSchema` minimum
+    #[darling(default)]
+    min: Option<syn::Lit>,
+    /// `$jsonSchema` maximum
+    #[darling(default)]
+    max: Option<syn::Lit>,
+    /// minLength for strings, minItems for arrays
+    #[darling(default)]
+    min_len: Option<u64>,
+    /// maxLength for strings, maxItems for arrays
+    #[darling(default)]
+    max_len: Option<u64>,
+    /// regex strings have to match
+    #[darling(default)]
+    pattern: Option<String>,
+    /// allowed values, e.g one_of = ["admin", "user"]
+    #[darling(default)]
+    one_of: Option<syn::ExprArray>,
+}
+
+/// seconds in a ttl like 30d, plain numbers are seconds
+fn parse_ttl(ttl: &str) -> Option<u64> {
+    let ttl = ttl.trim();
+    let (num, unit) = match ttl.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => ttl.split_at(i),
+        None => (ttl, "s"),
+    };
+    let mult = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    num.parse::<u64>().ok().map(|n| n * mult)
 }
 
 pub fn main(input: DeriveInput) -> TokenStream {
@@ -57,8 +157,19 @@ pub fn main(input: DeriveInput) -> TokenStream {
     // [(name, type)]
     let mut field_types = vec![];
     let mut field_names = vec![];
+    // where each field is stored, `_id` for id and serde renames applied
+    let mut field_paths: Vec<String> = vec![];
+    let rename_all = schema::serde_rename_all(&opts.attrs);
+    let mut field_tys = vec![];
     let mut unique_fields = vec![];
-    let mut unique_if_same_fields = vec![];
+    let mut unique_if_same_specs = vec![];
+    let mut snapshot_field = None;
+    let mut version_field = None;
+    let mut sequence_fills = vec![];
+    let mut sequence_specs = vec![];
+    let mut indexes = vec![];
+    let mut schema_props = serde_json::Map::new();
+    let mut schema_required = vec![];
 
     let on_delete_vals = OnDelete::VARIANTS;
 
@@ -72,7 +183,24 @@ pub fn main(input: DeriveInput) -> TokenStream {
             let name = f.ident.as_ref().unwrap().to_string();
             let _ty = &f.ty;
             let field_name_ident = format_ident!("{}", name);
+            if f.snapshot {
+                if snapshot_field.is_some() {
+                    panic!("Only one #[tumongo(snapshot)] field is allowed");
+                }
+                snapshot_field = Some(field_name_ident);
+                return;
+            }
+            let serde_field = schema::serde_field(&f.attrs);
+            let path = match name.as_str() {
+                "id" => "_id".to_string(),
+                _ => serde_field.stored_name(&name, rename_all.as_deref()),
+            };
+            if ["created_at", "updated_at", "deleted_at"].contains(&name.as_str()) && path != name {
+                panic!("{name} is stored as {path}, tumongo writes it as {name}. add #[serde(rename = \"{name}\")]");
+            }
             field_names.push(field_name_ident.clone());
+            field_paths.push(path.clone());
+            field_tys.push(_ty.clone());
             let ty_tkn = quote! {stringify!(#_ty)};
             field_types.push(ty_tkn.clone());
             if name == "created_at" || name == "updated_at" {
@@ -80,20 +208,103 @@ pub fn main(input: DeriveInput) -> TokenStream {
                     panic!("created_at and updated_at fields should be of type Tumongo::DateTime");
                 }
             }
-            if f.unique{
-                unique_fields.push(field_name_ident.clone());
+            let (mut prop, optional) = schema::type_schema(_ty);
+            if name == "id" {
+                prop = serde_json::Map::new();
+                prop.insert("bsonType".into(), "objectId".into());
+            }
+            if let Some(min) = &f.min {
+                prop.insert("minimum".into(), schema::lit_value(min));
+            }
+            if let Some(max) = &f.max {
+                prop.insert("maximum".into(), schema::lit_value(max));
+            }
+            let (min_key, max_key) = if schema::is_array(&prop) {
+                ("minItems", "maxItems")
+            } else {
+                ("minLength", "maxLength")
+            };
+            if let Some(min_len) = f.min_len {
+                prop.insert(min_key.into(), min_len.into());
+            }
+            if let Some(max_len) = f.max_len {
+                prop.insert(max_key.into(), max_len.into());
+            }
+            if let Some(pattern) = &f.pattern {
+                prop.insert("pattern".into(), pattern.clone().into());
+            }
+            if let Some(one_of) = &f.one_of {
+                let mut vals: Vec<serde_json::Value> = one_of
+                    .elems
+                    .iter()
+                    .map(|expr| match expr {
+                        syn::Expr::Lit(lit) => schema::lit_value(&lit.lit),
+                        _ => panic!("one_of on {name} should only contain literals"),
+                    })
+                    .collect();
+                if optional {
+                    vals.push(serde_json::Value::Null);
+                }
+                prop.insert("enum".into(), vals.into());
+            }
+            if !serde_field.skip {
+                if !optional && !serde_field.maybe_missing && name != "id" {
+                    schema_required.push(path.clone());
+                }
+                schema_props.insert(path.clone(), prop.into());
             }
-            if let Some(same_field) = f.unique_if_same.as_ref(){
-                let same_field_ident = format_ident!("{}", same_field);
-                unique_if_same_fields.push(quote!{
-                    let field_val = &self.#field_name_ident;
-                    let same_field_val = &self.#same_field_ident;
-                    if coll.find_one(doc!{#name: field_val, "_id": {"$ne": self.id}, #same_field: same_field_val}).await?.is_some(){
-                        return Err(format!("UNIQUE FIELD ERROR: another doc with [{} = {field_val:?}] and [{} = {same_field_val:?}]  already exists in {} collection.", #name, #same_field, Self::coll_name()).into()); 
-                    }
 
+            if let Some(ttl) = &f.ttl {
+                if !ty_tkn.to_string().contains("DateTime") {
+                    panic!("ttl field {name} should be a DateTime");
+                }
+                let Some(ttl_secs) = parse_ttl(ttl) else {
+                    panic!("Invalid ttl {ttl:?} on {name}, use e.g 30d, 12h, 90m or 3600s");
+                };
+                indexes.push(IndexSpec {
+                    keys: vec![(name.clone(), "1".to_string())],
+                    ttl_secs: Some(ttl_secs),
+                    ..Default::default()
                 });
             }
+            if f.version {
+                if version_field.is_some() {
+                    panic!("Only one #[tumongo(version)] field is allowed");
+                }
+                let ty_str = ty_tkn.to_string();
+                if !ty_str.contains("i64") && !ty_str.contains("i32") {
+                    panic!("version field should be of type i64 or i32");
+                }
+                version_field = Some(field_name_ident.clone());
+            }
+            if let Some(seq_name) = f.sequence.as_ref() {
+                let ty_str = ty_tkn.to_string();
+                if !["i64", "i32", "u64", "u32"].iter().any(|t| ty_str.contains(t)) {
+                    panic!("sequence field {name} should be an integer");
+                }
+                sequence_specs.push((path.clone(), seq_name.clone(), f.sequence_scope.clone()));
+                let scope = match f.sequence_scope.as_ref() {
+                    Some(scope_field) => {
+                        let scope_ident = format_ident!("{}", scope_field);
+                        quote! { Some(bson::to_bson(&self.#scope_ident)?) }
+                    }
+                    None => quote! { None },
+                };
+                sequence_fills.push(quote! {
+                    if self.#field_name_ident == 0 {
+                        let n = Tumongo::next_sequence(&Self::database(db)?, &mut *sess, #seq_name, #scope).await?;
+                        self.#field_name_ident = n.try_into()?;
+                    }
+                });
+            } else if f.sequence_scope.is_some() {
+                panic!("sequence_scope on {name} needs a sequence");
+            }
+            if f.unique{
+                unique_fields.push((field_name_ident.clone(), path.clone()));
+            }
+            if let Some(same_field) = f.unique_if_same.as_ref(){
+                unique_if_same_specs.push((field_name_ident.clone(), path.clone(), same_field.clone()));
+            }
             if f.fk {
                 if f.coll.is_none() {
                     panic!("Specify collection name (coll)");
@@ -106,7 +317,7 @@ pub fn main(input: DeriveInput) -> TokenStream {
                     }
                 }
                 let mut fk_field = FkField {
-                    field_name: name.clone(),
+                    field_name: path.clone(),
                     coll: f.coll.as_ref().unwrap().to_string(),
                     on_delete: f.on_delete.clone(),
                 };
@@ -134,7 +345,7 @@ pub fn main(input: DeriveInput) -> TokenStream {
                     }
                 }
                 let ref_field = FkField {
-                    field_name: name.clone(),
+                    field_name: path.clone(),
                     coll: f.coll.as_ref().unwrap().to_string(),
                     on_delete: f.on_delete.clone(),
                 };
@@ -156,26 +367,483 @@ pub fn main(input: DeriveInput) -> TokenStream {
     if !required_fields_exist {
         panic!("struct should contain {required_fields:?}");
     }
-    let unique_fields_str: Vec<String> = unique_fields.iter().map(|x| x.to_string()).collect();
+    if opts.soft_delete {
+        let deleted_at_ok = field_types
+            .iter()
+            .zip(field_names.iter())
+            .any(|(ty, name)| name == "deleted_at" && ty.to_string().contains("Option") && ty.to_string().contains("DateTime"));
+        if !deleted_at_ok {
+            panic!("soft_delete models should contain deleted_at: Option<tumongo::DateTime>");
+        }
+        let mut reg = SOFT_DELETE_COLLS.lock().expect("Failed to lock soft delete reg.");
+        if !reg.contains(coll_name) {
+            reg.push(coll_name.clone());
+        }
+    }
+    if let Some(pref) = &opts.read_preference {
+        let prefs = ["primary", "primary_preferred", "secondary", "secondary_preferred", "nearest"];
+        if !prefs.contains(&pref.as_str()) {
+            panic!("read_preference should be one of {prefs:?}");
+        }
+    }
+    if let Some(level) = &opts.read_concern {
+        let levels = ["local", "available", "majority", "linearizable", "snapshot"];
+        if !levels.contains(&level.as_str()) {
+            panic!("read_concern should be one of {levels:?}");
+        }
+    }
+    if opts.write_concern.as_ref().is_some_and(|w| w.is_empty()) {
+        panic!("write_concern should be majority, a node count or a tag set name");
+    }
+    if let Some(tenant_field) = &opts.tenant_field {
+        if !field_names.iter().any(|name| name == tenant_field) {
+            panic!("tenant_field {tenant_field} is not a field of the struct");
+        }
+    }
+    let path_of = |name: &str| {
+        let i = field_names.iter().position(|f| f == name).expect("unknown field");
+        field_paths[i].clone()
+    };
+    let tenant_path = opts.tenant_field.as_deref().map(path_of);
+    for index in &opts.indexes {
+        let mut keys = vec![];
+        for expr in &index.fields.elems {
+            let syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(path), .. }) = expr else {
+                panic!("index fields should be string literals");
+            };
+            let path = path.value();
+            let (path, dir) = match path.strip_prefix('-') {
+                Some(path) => (path.to_string(), "-1"),
+                None => (path, "1"),
+            };
+            let root = path.split('.').next().unwrap_or_default();
+            if !field_names.iter().any(|name| name == root) {
+                panic!("index field {path} is not a field of the struct");
+            }
+            let kind = match index.kind.as_deref() {
+                None => dir.to_string(),
+                Some(kind @ ("text" | "2dsphere")) => kind.to_string(),
+                Some(kind) => panic!("index kind should be text or 2dsphere, got {kind}"),
+            };
+            let path = if path == "id" { "_id".to_string() } else { path };
+            keys.push((path, kind));
+        }
+        if keys.is_empty() {
+            panic!("index needs at least one field");
+        }
+        if let Some(partial) = &index.partial {
+            if serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(partial).is_err() {
+                panic!("index partial should be a json object, got {partial}");
+            }
+        }
+        indexes.push(IndexSpec {
+            name: index.name.clone(),
+            keys,
+            unique: index.unique,
+            sparse: index.sparse,
+            ttl_secs: None,
+            partial: index.partial.clone(),
+        });
+    }
+    let indexes_str = serde_json::to_string(&indexes).unwrap();
+    let schema_str = serde_json::json!({
+        "bsonType": "object",
+        "title": coll_name,
+        "required": schema_required,
+        "properties": schema_props,
+    })
+    .to_string();
+
+    let route = CollRoute {
+        conn: opts.conn.clone(),
+        db: opts.db.clone(),
+        read_preference: opts.read_preference.clone(),
+        read_concern: opts.read_concern.clone(),
+        write_concern: opts.write_concern.clone(),
+        tenant_field: tenant_path.clone(),
+    };
+    if route.conn.is_some()
+        || route.db.is_some()
+        || route.read_preference.is_some()
+        || route.read_concern.is_some()
+        || route.write_concern.is_some()
+        || route.tenant_field.is_some()
+    {
+        COLL_ROUTES
+            .lock()
+            .expect("Failed to lock coll route reg.")
+            .insert(coll_name.clone(), route);
+    }
+    let soft_delete = opts.soft_delete;
+    let tenant_field = match &tenant_path {
+        Some(field) => quote! { Some(#field) },
+        None => quote! { None },
+    };
+    let vis = &opts.vis;
+    let fields_mod_name = format_ident!("{}_fields", snake_case(&struct_name.to_string()));
+    let unique_if_same_fields: Vec<_> = unique_if_same_specs
+        .iter()
+        .map(|(field_name_ident, path, same_field)| {
+            if !field_names.iter().any(|name| name == same_field) {
+                panic!("unique_if_same {same_field} is not a field of the struct");
+            }
+            let same_field_ident = format_ident!("{}", same_field);
+            let same_path = path_of(same_field);
+            quote!{
+                let field_val = &self.#field_name_ident;
+                let same_field_val = &self.#same_field_ident;
+                if coll.find_one(doc!{#path: field_val, "_id": {"$ne": self.id}, #same_path: same_field_val}).session(&mut *sess).await?.is_some(){
+                    return Err(format!("UNIQUE FIELD ERROR: another doc with [{} = {field_val:?}] and [{} = {same_field_val:?}]  already exists in {} collection.", #path, #same_path, Self::coll_name()).into()); 
+                }
+
+            }
+        })
+        .collect();
+    let unique_if_same_pairs: Vec<_> = unique_if_same_specs
+        .iter()
+        .map(|(_, path, same_field)| {
+            let same_path = path_of(same_field);
+            quote! { (#path, #same_path) }
+        })
+        .collect();
+    let unique_fields_str: Vec<String> = unique_fields.iter().map(|(_, path)| path.clone()).collect();
+    let unique_fields: Vec<_> = unique_fields.into_iter().map(|(ident, _)| ident).collect();
+    if !unique_fields_str.is_empty() {
+        UNIQUE_FIELDS
+            .lock()
+            .expect("Failed to lock unique reg.")
+            .insert(coll_name.clone(), unique_fields_str.clone());
+    }
     let mod_name = format_ident!("__{}__", struct_name.to_string().to_lowercase());
+    let (snapshot_get, snapshot_set) = match snapshot_field {
+        Some(ref f) => (
+            quote! { self.#f.get() },
+            quote! {
+                let dok = self.to_doc();
+                self.#f.set(dok);
+            },
+        ),
+        None => (quote! { None }, quote! {}),
+    };
+    let version_field_str = match &version_field {
+        Some(f) => {
+            let f_str = path_of(&f.to_string());
+            quote! { Some(#f_str) }
+        }
+        None => quote! { None },
+    };
+    let sequence_specs: Vec<_> = sequence_specs
+        .iter()
+        .map(|(path, seq_name, scope)| {
+            let scope = match scope {
+                Some(scope) => {
+                    if !field_names.iter().any(|name| name == scope) {
+                        panic!("sequence_scope {scope} is not a field of the struct");
+                    }
+                    let scope = path_of(scope);
+                    quote! { Some(#scope) }
+                }
+                None => quote! { None },
+            };
+            quote! { (#path, #seq_name, #scope) }
+        })
+        .collect();
+    let (version_filter, version_check) = match version_field {
+        Some(ref f) => {
+            let f_str = path_of(&f.to_string());
+            (
+                quote! {
+                    filter.insert(#f_str, self.#f);
+                    set.insert(#f_str, self.#f + 1);
+                },
+                quote! {
+                    if res.matched_count == 0 {
+                        return Err(tumongo::Conflict {
+                            coll: Self::coll_name(),
+                            id: _id.clone(),
+                            version: i64::from(self.#f),
+                        }
+                        .into());
+                    }
+                    self.#f += 1;
+                },
+            )
+        }
+        None => (quote! {}, quote! {}),
+    };
+    let history = opts.history;
+    let (history_save_before, history_save_after) = if history {
+        (
+            quote! {
+                let before = match self.id {
+                    Some(_id) => Tumongo::coll::<Document>(db, &Self::coll_name())?
+                        .find_one(doc! {"_id": _id})
+                        .session(&mut *sess)
+                        .await?
+                        .unwrap_or_default(),
+                    None => Document::new(),
+                };
+                let op = if self.id.is_some() {
+                    tumongo::HistoryOp::Update
+                } else {
+                    tumongo::HistoryOp::Insert
+                };
+            },
+            quote! {
+                let after = Tumongo::coll::<Document>(db, &Self::coll_name())?
+                    .find_one(doc! {"_id": tx})
+                    .session(&mut *sess)
+                    .await?
+                    .unwrap_or_default();
+                Tumongo::record_history(db, &mut *sess, &Self::coll_name(), &tx, op, &before, &after).await?;
+            },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+    let (history_delete_before, history_delete_after) = if history {
+        (
+            quote! {
+                let before = Tumongo::coll::<Document>(db, &coll_name)?
+                    .find_one(doc! {"_id": _id})
+                    .session(&mut *sess)
+                    .await?
+                    .unwrap_or_default();
+            },
+            quote! {
+                Tumongo::record_history(&db, &mut *sess, &coll_name, &_id, tumongo::HistoryOp::Delete, &before, &Document::new()).await?;
+            },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+    let history_soft = |op: proc_macro2::TokenStream| {
+        if history {
+            (
+                quote! { let before = self.to_doc(); },
+                quote! {
+                    Tumongo::record_history(&db, &mut *sess, &Self::coll_name(), &_id, #op, &before, &self.to_doc()).await?;
+                },
+            )
+        } else {
+            (quote! {}, quote! {})
+        }
+    };
+    let (history_soft_delete_before, history_soft_delete_after) = history_soft(quote! { tumongo::HistoryOp::Delete });
+    let (history_restore_before, history_restore_after) = history_soft(quote! { tumongo::HistoryOp::Restore });
+    let hard_delete_body = quote! {
+        let Some(_id) = self.id else {
+            return Ok(());
+        };
+        let fk_fields = Tumongo::fk_fields();
+        let coll_name = Self::coll_name();
+        #history_delete_before
+        Tumongo::delete(&db, &coll_name, &_id, &fk_fields, &mut *sess, false).await?;
+        #history_delete_after
+        Ok(())
+    };
+    let history_fns = if history && opts.tenant_field.is_some() {
+        quote! {
+            /// history records of a doc of the scope's tenant, oldest first
+            pub async fn history(scope: &tumongo::TenantScope, id: &ObjectId) -> Res<Vec<tumongo::HistoryRecord>> {
+                scope.history::<Self>(id).await
+            }
+
+            /// puts the doc of the scope's tenant back to how it was at history record `version`
+            pub async fn revert_to(scope: &tumongo::TenantScope, id: &ObjectId, version: i64) -> Res<Self> {
+                scope.revert_to::<Self>(id, version).await
+            }
+        }
+    } else if history {
+        quote! {
+            /// history records of a doc, oldest first
+            pub async fn history(db: &db::Database, id: &ObjectId) -> Res<Vec<tumongo::HistoryRecord>> {
+                Tumongo::history(db, &Self::coll_name(), id).await
+            }
+
+            /// puts the doc back to how it was at history record `version`
+            pub async fn revert_to(db: &db::Database, id: &ObjectId, version: i64) -> Res<Self> {
+                let dok = Tumongo::revert_to(db, &Self::coll_name(), id, version).await?;
+                Ok(Self::from_doc(dok)?)
+            }
+        }
+    } else {
+        quote! {}
+    };
+    let delete_fns = if soft_delete {
+        quote! {
+            /// soft delete, sets deleted_at here and on the on_delete = "cascade" children
+            pub async fn delete(&mut self, db: &db::Database) -> db::error::Result<()> {
+                let mut sess = Self::database(db)?.client().start_session().await?;
+                self.delete_with_session(db, &mut sess).await
+            }
+
+            pub async fn delete_with_session(&mut self, db: &db::Database, sess: &mut db::ClientSession) -> db::error::Result<()> {
+                let Some(_id) = self.id else {
+                    return Ok(());
+                };
+                let at = bson::DateTime::now();
+                #history_soft_delete_before
+                Tumongo::soft_delete(&db, &Self::coll_name(), &_id, Tumongo::fk_fields(), &mut *sess, at).await?;
+                self.deleted_at = Some(tumongo::DateTime::from_millis(at.timestamp_millis()));
+                #history_soft_delete_after
+                self.take_snapshot();
+                Ok(())
+            }
+
+            /// brings back the doc and the children deleted along with it
+            pub async fn restore(&mut self, db: &db::Database) -> db::error::Result<()> {
+                let mut sess = Self::database(db)?.client().start_session().await?;
+                self.restore_with_session(db, &mut sess).await
+            }
+
+            pub async fn restore_with_session(&mut self, db: &db::Database, sess: &mut db::ClientSession) -> db::error::Result<()> {
+                let (Some(_id), Some(at)) = (self.id, self.deleted_at.as_ref()) else {
+                    return Ok(());
+                };
+                let at = **at;
+                #history_restore_before
+                Tumongo::restore(&db, &Self::coll_name(), &_id, Tumongo::fk_fields(), &mut *sess, at).await?;
+                self.deleted_at = None;
+                #history_restore_after
+                self.take_snapshot();
+                Ok(())
+            }
+
+            /// hard delete, applies on_delete to the children
+            pub async fn purge(&self, db: &db::Database) -> db::error::Result<()> {
+                let mut sess = Self::database(db)?.client().start_session().await?;
+                self.purge_with_session(db, &mut sess).await
+            }
+
+            pub async fn purge_with_session(&self, db: &db::Database, sess: &mut db::ClientSession) -> db::error::Result<()> {
+                #hard_delete_body
+            }
+
+            /// queries that also see soft deleted docs
+            pub fn with_deleted() -> tumongo::DeletedScope<Self> {
+                tumongo::DeletedScope::new(tumongo::Deleted::Include)
+            }
+
+            /// queries that only see soft deleted docs
+            pub fn only_deleted() -> tumongo::DeletedScope<Self> {
+                tumongo::DeletedScope::new(tumongo::Deleted::Only)
+            }
+        }
+    } else {
+        quote! {
+            pub async fn delete(&self, db: &db::Database) -> db::error::Result<()> {
+                let mut sess = Self::database(db)?.client().start_session().await?;
+                self.delete_with_session(db, &mut sess).await
+            }
+
+            pub async fn delete_with_session(&self, db: &db::Database, sess: &mut db::ClientSession) -> db::error::Result<()> {
+                #hard_delete_body
+            }
+        }
+    };
 
     let expanded = quote_spanned! { struct_name.span()=>
 
+        #[doc = concat!("typed field paths of `", stringify!(#struct_name), "` for `query()` filters and sorts")]
+        #[allow(non_upper_case_globals)]
+        #vis mod #fields_mod_name {
+            #[allow(unused_imports)]
+            use super::*;
+            #(
+                pub const #field_names: tumongo::Field<super::#struct_name, #field_tys> = tumongo::Field::new(#field_paths);
+            )*
+        }
+
         mod #mod_name {
-            use tumongo::{ Tumongo,
-                db::{self, bson::{self, doc, Document}, Database},
-                 futures_util::TryStreamExt,
+            #[allow(unused_imports)]
+            use tumongo::{ Tumongo, Model,
+                db::{self, bson::{self, doc, Document, oid::ObjectId}, Database},
                 serde_json::{self,Value}
             };
             type Res<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
+            #[tumongo::async_trait::async_trait]
+            impl Model for super::#struct_name {
+                fn coll_name() -> String {
+                    #coll_name.to_string()
+                }
+                fn soft_delete() -> bool {
+                    #soft_delete
+                }
+                fn tenant_field() -> Option<&'static str> {
+                    #tenant_field
+                }
+                fn history() -> bool {
+                    #history
+                }
+                fn version_field() -> Option<&'static str> {
+                    #version_field_str
+                }
+                fn unique_fields() -> Vec<&'static str> {
+                    vec![#(#unique_fields_str),*]
+                }
+                fn unique_if_same_fields() -> Vec<(&'static str, &'static str)> {
+                    vec![#(#unique_if_same_pairs),*]
+                }
+                fn changes(&self) -> (Document, Document) {
+                    super::#struct_name::changes(self)
+                }
+                fn sequence_fields() -> Vec<(&'static str, &'static str, Option<&'static str>)> {
+                    vec![#(#sequence_specs),*]
+                }
+                fn indexes() -> Vec<tumongo::IndexSpec> {
+                    tumongo::serde_json::from_str(#indexes_str).expect("Invalid index specs")
+                }
+                fn json_schema() -> Document {
+                    Tumongo::parse_schema(#schema_str)
+                }
+                fn id(&self) -> Option<ObjectId> {
+                    self.id
+                }
+                fn set_id(&mut self, id: ObjectId) {
+                    self.id.replace(id);
+                }
+                fn to_doc(&self) -> Document {
+                    super::#struct_name::to_doc(self)
+                }
+                fn take_snapshot(&mut self) {
+                    #snapshot_set
+                }
+                async fn save(&mut self, db: &Database) -> Res<()> {
+                    super::#struct_name::save(self, db).await
+                }
+                async fn delete(&mut self, db: &Database) -> Res<()> {
+                    Ok(super::#struct_name::delete(self, db).await?)
+                }
+                async fn save_with_session(&mut self, db: &Database, sess: &mut db::ClientSession) -> Res<()> {
+                    super::#struct_name::save_with_session(self, db, sess).await
+                }
+                async fn delete_with_session(&mut self, db: &Database, sess: &mut db::ClientSession) -> Res<()> {
+                    Ok(super::#struct_name::delete_with_session(self, db, sess).await?)
+                }
+            }
+
             impl super::#struct_name {
                 pub fn coll_name() -> String {
                     #coll_name.to_string()
                 }
-                pub fn collection(db: &db::Database) -> db::Collection<Self> {
+                /// the database this model lives in, `db` unless routed with conn/db.
+                /// fails if the model's conn isn't connected
+                pub fn database(db: &db::Database) -> db::error::Result<db::Database> {
+                    Tumongo::route_db(db, &Self::coll_name())
+                }
+                /// [Self::database] on the default connection
+                pub fn db() -> db::error::Result<db::Database> {
+                    Self::database(tumongo::dbase())
+                }
+                pub fn collection(db: &db::Database) -> db::error::Result<db::Collection<Self>> {
                     let coll_name = Self::coll_name();
-                    db.collection::<Self>(&coll_name)
+                    Tumongo::coll::<Self>(db, &coll_name)
+                }
+                /// [Self::collection] with per call read preference/concerns, unset ones come from the model
+                pub fn collection_with(db: &db::Database, opts: db::options::CollectionOptions) -> db::error::Result<db::Collection<Self>> {
+                    Tumongo::coll_with::<Self>(db, &Self::coll_name(), opts)
                 }
 
                 pub fn get_fields() -> Vec<String>{
@@ -205,6 +873,61 @@ pub fn main(input: DeriveInput) -> TokenStream {
                 }
 
                 
+                #[doc = concat!("typed find builder, use with the `", stringify!(#fields_mod_name), "` field paths")]
+                pub fn query() -> tumongo::Query<Self> {
+                    tumongo::Query::new()
+                }
+
+                /// typed update of the docs matching `filter` (a Document or tumongo::Filter)
+                pub fn update(db: &db::Database, filter: impl Into<Document>) -> tumongo::Update<'_, Self> {
+                    tumongo::Update::new(db, filter.into())
+                }
+
+                /// typed update of this doc in the db, the instance itself is left as is.
+                /// tenant models only match the doc within the instance's tenant
+                pub fn update_fields<'a>(&self, db: &'a db::Database) -> tumongo::Update<'a, Self> {
+                    let mut filter = doc! {"_id": self.id};
+                    if let Some(field) = <Self as Model>::tenant_field() {
+                        filter.insert(field, self.to_doc().get(field).cloned().unwrap_or(bson::Bson::Null));
+                    }
+                    tumongo::Update::new(db, filter)
+                }
+
+                /// matching docs loaded as the projection `P`, only its fields are fetched
+                pub async fn find_as<P: tumongo::Projection<Self>>(
+                    _db: &Database,
+                    filter: Document,
+                ) -> Res<Vec<P>> {
+                    Tumongo::find_as::<Self, P>(_db, filter, Default::default()).await
+                }
+
+                /// page `page` (from 1) of the matching docs, sorted by _id
+                pub async fn paginate(
+                    _db: &Database,
+                    filter: Document,
+                    page: u64,
+                    per_page: u64,
+                ) -> Res<tumongo::Page<Self>> {
+                    Tumongo::paginate(_db, filter, page, per_page).await
+                }
+
+                /// keyset pagination, `sort_field` may start with "-" for descending and
+                /// `cursor` is the `next` of the previous page
+                pub async fn paginate_after(
+                    _db: &Database,
+                    filter: Document,
+                    sort_field: &str,
+                    cursor: Option<&str>,
+                    limit: i64,
+                ) -> Res<tumongo::CursorPage<Self>> {
+                    Tumongo::paginate_after(_db, filter, sort_field, cursor, limit).await
+                }
+
+                /// aggregation pipeline builder over this collection
+                pub fn aggregate() -> tumongo::Aggregate<Self> {
+                    tumongo::Aggregate::new()
+                }
+
                 pub fn test(){
                     println!("\n[TEST] {:#?}", Tumongo::fk_fields());
                 }
@@ -213,68 +936,133 @@ pub fn main(input: DeriveInput) -> TokenStream {
                     _db: &Database,
                     filter: Document, skip: Option<u64>, limit: Option<i64>, sort: Option<Document>
                 ) -> Res<Vec<Self>> {
-                    let coll = _db
-                    .collection::<Document>(&Self::coll_name());
-
-                    let mut query = coll
-                    .find(filter.clone());
-                    
-                    if let Some(skip) = skip{
-                        query = query.skip(skip);
-                    }
-                    if let Some(limit) = limit{
-                        query = query.limit(limit);
-                    }if let Some(sort) = sort{
-                        query = query.sort(sort);
+                    let opts = tumongo::FindOpts { skip, limit, sort, ..Default::default() };
+                    Tumongo::find(_db, filter, opts).await
+                }
+                /// lazily yields the matching docs with errors as items, for walking big collections
+                pub async fn stream(
+                    _db: &Database,
+                    filter: Document,
+                    opts: tumongo::FindOpts,
+                ) -> Res<tumongo::futures_util::stream::BoxStream<'static, Res<Self>>> {
+                    Tumongo::stream(_db, filter, opts).await
+                }
+                    pub async fn find_one(
+                        _db: &Database,
+                        filter: Document,
+                    ) -> Res<Self> {
+                        Tumongo::find_one(_db, filter, tumongo::FindOpts::strict()).await
                     }
-                    
-                    let mut res = query
-                        .await?;
 
-                    let mut res_doks = vec![];
+                /// `find` inside `sess`, e.g a transaction
+                pub async fn find_with_session(
+                    _db: &Database,
+                    filter: Document,
+                    opts: tumongo::FindOpts,
+                    sess: &mut db::ClientSession,
+                ) -> Res<Vec<Self>> {
+                    Tumongo::find_with_session(_db, filter, opts, sess).await
+                }
 
-                    while let Some(dok) = res.try_next().await? {
-                        let _id = dok.get_object_id("_id").expect("no _id");
+                pub async fn find_one_with_session(
+                    _db: &Database,
+                    filter: Document,
+                    sess: &mut db::ClientSession,
+                ) -> Res<Self> {
+                    Tumongo::find_one_with_session(_db, filter, tumongo::FindOpts::strict(), sess).await
+                }
 
-                        match bson::from_document::<Self>(dok.clone()){
-                        Ok(mut dok) =>{
-                            dok.id.replace(_id);
-                            res_doks.push(dok);
-                        },
-                        Err(err)=>{
-                            eprintln!("[find] [{}] Error serializing dok {dok:?}", Self::coll_name());
-                            eprintln!("{err:?}");
-                        }}
+                pub async fn find_by_id(_db: &Database, id: &ObjectId) -> Res<Self> {
+                    Tumongo::find_one(_db, doc! {"_id": id}, tumongo::FindOpts::strict()).await
+                }
 
-                    };
+                /// docs for `ids` in the same order, missing ones are left out
+                pub async fn find_by_ids(_db: &Database, ids: &[ObjectId]) -> Res<Vec<Self>> {
+                    Tumongo::find_by_ids(_db, ids).await
+                }
 
-                    Ok(res_doks)
+                pub async fn count(_db: &Database, filter: Document) -> Res<u64> {
+                    Tumongo::count::<Self>(_db, filter).await
                 }
-                    pub async fn find_one(
-                        _db: &Database,
-                        filter: Document,
-                    ) -> Res<Self> {
-                        let res = _db
-                            .collection::<Document>(&Self::coll_name())
-                            .find_one(filter.clone())
-                            .await?;
-                        if let Some(dok) = res {
-                            let _id = dok.get_object_id("_id").unwrap();
-                            let mut dok: Self = bson::from_document(dok.clone()).expect(&format!("Failed to convert {dok:?} to {:?}", stringify!(#struct_name)));
-                            dok.id.replace(_id);
-                            Ok(dok)
-                        } else {
-                            return Err(format!("No matching item in {} for {filter:?}", Self::coll_name()).into());
-                        }
+
+                /// from collection metadata, includes soft deleted docs
+                pub async fn estimated_count(_db: &Database) -> Res<u64> {
+                    Tumongo::estimated_count::<Self>(_db).await
+                }
+
+                pub async fn exists(_db: &Database, filter: Document) -> Res<bool> {
+                    Tumongo::exists::<Self>(_db, filter).await
+                }
+
+                /// distinct values of `field` among the matching docs
+                pub async fn distinct<V: tumongo::serde::de::DeserializeOwned>(
+                    _db: &Database,
+                    field: &str,
+                    filter: Document,
+                ) -> Res<Vec<V>> {
+                    Tumongo::distinct::<Self, V>(_db, field, filter).await
+                }
+
+                /// find with all the options, also reports the docs that failed to deserialize
+                pub async fn find_with(
+                    _db: &Database,
+                    filter: Document,
+                    opts: tumongo::FindOpts,
+                ) -> Res<tumongo::Found<Self>> {
+                    Tumongo::find_all(_db, filter, opts).await
+                }
+
+                /// matching docs and the ones that failed to deserialize
+                pub async fn find_collect(
+                    _db: &Database,
+                    filter: Document,
+                    mut opts: tumongo::FindOpts,
+                ) -> Res<(Vec<Self>, Vec<tumongo::DeserializeFailure>)> {
+                    opts.mode = tumongo::DeserializeMode::Collect;
+                    let found = Tumongo::find_all(_db, filter, opts).await?;
+                    Ok((found.items, found.failures))
+                }
+
+                pub async fn find_one_with(
+                    _db: &Database,
+                    filter: Document,
+                    opts: tumongo::FindOpts,
+                ) -> Res<Self> {
+                    Tumongo::find_one(_db, filter, opts).await
+                }
+
+                    /// fields changed since the doc was loaded or last saved.
+                    ///
+                    /// without a #[tumongo(snapshot)] field every field counts as changed
+                    pub fn changed_fields(&self) -> Vec<String> {
+                        let (set, unset) = self.changes();
+                        set.keys().chain(unset.keys()).cloned().collect()
+                    }
+
+                    /// ($set, $unset) docs relative to the snapshot
+                    fn changes(&self) -> (Document, Document) {
+                        Tumongo::diff(#snapshot_get, &self.to_doc())
                     }
 
-                    /// also updates the instance's id field
+                    /// also updates the instance's id field.
+                    ///
+                    /// existing docs only get their changed fields written, nothing is sent if none changed
                     pub async fn save(&mut self, db: &db::Database) -> Res<()> {
-                        
-                        let coll = Self::collection(db);
+                        let mut sess = Self::database(db)?.client().start_session().await?;
+                        self.save_with_session(db, &mut sess).await
+                    }
+
+                    /// `save` with every read and write in `sess`
+                    pub async fn save_with_session(&mut self, db: &db::Database, sess: &mut db::ClientSession) -> Res<()> {
+                        let (mut set, unset) = self.changes();
+                        if self.id.is_some() && set.is_empty() && unset.is_empty() {
+                            return Ok(());
+                        }
+
+                        let coll = Self::collection(db)?;
                         #(
                             let _val = &self.#unique_fields;
-                            if coll.find_one(doc!{#unique_fields_str: _val, "_id": {"$ne": self.id}}).await?.is_some(){
+                            if coll.find_one(doc!{#unique_fields_str: _val, "_id": {"$ne": self.id}}).session(&mut *sess).await?.is_some(){
                                 return Err(format!("UNIQUE FIELD ERROR: another doc with {} = {_val:?} already exists in {} collection.", #unique_fields_str, Self::coll_name()).into()); 
                             }
                         )*
@@ -282,19 +1070,26 @@ pub fn main(input: DeriveInput) -> TokenStream {
                         // handle unique_if_same fields
                         #(#unique_if_same_fields)*
 
+                        #history_save_before
                         self.updated_at = tumongo::DateTime::now();
                         let tx = if let Some(ref _id) = self.id {
-                            coll.update_one(
-                                doc! {  "_id": _id },
-                                doc! {
-                                    "$set": self.to_doc()
-                                },
-                            )
-                            .await?;
+                            set.insert("updated_at", *self.updated_at);
+                            #[allow(unused_mut)]
+                            let mut filter = doc! { "_id": _id };
+                            #version_filter
+                            let mut update = doc! { "$set": set };
+                            if !unset.is_empty() {
+                                update.insert("$unset", unset);
+                            }
+                            #[allow(unused_variables)]
+                            let res = coll.update_one(filter, update).session(&mut *sess).await?;
+                            #version_check
                             _id.clone()
                         } else {
                             self.created_at = tumongo::DateTime::now();
+                            #(#sequence_fills)*
                             coll.insert_one(&*self)
+                                .session(&mut *sess)
                                 .await?
                                 .inserted_id
                                 .as_object_id()
@@ -303,14 +1098,51 @@ pub fn main(input: DeriveInput) -> TokenStream {
 
 
                         self.id.replace(tx);
+                        #history_save_after
+                        self.take_snapshot();
                         Ok(())
                     }
 
+                    /// updates the doc with the same `keys` values as `item` or inserts it,
+                    /// keys default to the #[tumongo(unique)] fields
+                    pub async fn upsert_by(db: &db::Database, keys: &[&str], item: &Self) -> Res<Self> {
+                        let unique: &[&str] = &[#(#unique_fields_str),*];
+                        let keys = if keys.is_empty() { unique } else { keys };
+                        Tumongo::upsert_by(db, keys, item).await
+                    }
+
+                    /// first doc matching `filter`, created from `default` if there's none
+                    pub async fn find_or_create(
+                        db: &db::Database,
+                        filter: Document,
+                        default: impl FnOnce() -> Self,
+                    ) -> Res<Self> {
+                        Tumongo::find_or_create(db, filter, default).await
+                    }
+
+                    /// saves the items in one bulk write, see tumongo::BulkWrite
+                    pub async fn save_many(db: &db::Database, items: &mut [Self]) -> Res<tumongo::BulkResult> {
+                        let mut bulk = tumongo::BulkWrite::new();
+                        for item in items.iter_mut() {
+                            bulk = bulk.save(item);
+                        }
+                        bulk.run(db).await
+                    }
+
+                    /// raw insert, skips unique checks, timestamps and ids, see save_many
                     pub async fn insert_many(
                         db: &db::Database,
                         list: &Vec<Self>,
                     ) -> Result<db::results::InsertManyResult, db::error::Error> {
-                        Self::collection(db).insert_many(list).await
+                        Self::collection(db)?.insert_many(list).await
+                    }
+
+                    pub async fn insert_many_with_session(
+                        db: &db::Database,
+                        list: &Vec<Self>,
+                        sess: &mut db::ClientSession,
+                    ) -> Result<db::results::InsertManyResult, db::error::Error> {
+                        Self::collection(db)?.insert_many(list).session(sess).await
                     }
                     pub fn to_value(&self) -> serde_json::Value {
                         serde_json::to_value(self).expect("Unvaluable")
@@ -324,15 +1156,9 @@ pub fn main(input: DeriveInput) -> TokenStream {
                         dok
                     }
 
-                    pub async fn delete(&self, db: &db::Database) -> db::error::Result<()> {
-                        if self.id.is_none(){
-                            return Ok(());
-                        }
-                        let mut sess = db.client().start_session().await?;
-                        let fk_fields = Tumongo::fk_fields();
-                        let coll_name = Self::coll_name();
-                        Tumongo::delete(&db, &coll_name, &self.id.unwrap(), &fk_fields, &mut sess, false).await
-                    }
+                    #delete_fns
+
+                    #history_fns
 
           /*           /// coll_name: target collection name
                 pub async fn populate(
@@ -382,9 +1208,66 @@ pub fn main(input: DeriveInput) -> TokenStream {
                 )
                 .await
             }
+
+            pub async fn populate_with_session(
+                &self,
+                _db: &Database,
+                coll_names: Option<&[&str]>,
+                sess: &mut db::ClientSession,
+            ) -> Document {
+                Tumongo::populate_with_session(
+                    &_db,
+                    self.to_doc(),
+                    &Self::coll_name(),
+                    coll_names,
+                    &Tumongo::fk_fields(),
+                    &Tumongo::ref_fields(),
+                    sess,
+                )
+                .await
+            }
             }
         }
 
     };
     TokenStream::from(expanded)
 }
+
+/// PersonInfo -> person_info
+pub(crate) fn snake_case(s: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in s.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ttl_units() {
+        assert_eq!(parse_ttl("3600"), Some(3600));
+        assert_eq!(parse_ttl("90s"), Some(90));
+        assert_eq!(parse_ttl("90m"), Some(90 * 60));
+        assert_eq!(parse_ttl(" 12h "), Some(12 * 60 * 60));
+        assert_eq!(parse_ttl("30d"), Some(30 * 24 * 60 * 60));
+        assert_eq!(parse_ttl("2w"), Some(2 * 7 * 24 * 60 * 60));
+    }
+
+    #[test]
+    fn parse_ttl_rejects_bad_input() {
+        assert_eq!(parse_ttl("30y"), None);
+        assert_eq!(parse_ttl("d"), None);
+        assert_eq!(parse_ttl("1.5h"), None);
+        assert_eq!(parse_ttl(""), None);
+    }
+}
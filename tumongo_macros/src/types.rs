@@ -15,6 +15,27 @@ pub struct FkField {
     pub on_delete: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CollRoute {
+    pub conn: Option<String>,
+    pub db: Option<String>,
+    pub read_preference: Option<String>,
+    pub read_concern: Option<String>,
+    pub write_concern: Option<String>,
+    pub tenant_field: Option<String>,
+}
+
+/// mirrors tumongo::IndexSpec
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct IndexSpec {
+    pub name: Option<String>,
+    pub keys: Vec<(String, String)>,
+    pub unique: bool,
+    pub sparse: bool,
+    pub ttl_secs: Option<u64>,
+    pub partial: Option<String>,
+}
+
 /// key = collection the field points to = field.coll
 /// 
 /// : if dealing with users, we'll access the other collections that contain 'users' as 'fk'
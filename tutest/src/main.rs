@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use tumongo::{db::bson::{oid::ObjectId, doc}, DateTime, TumongoModel};
+use tumongo::{db::bson::{oid::ObjectId, doc}, DateTime, Projection, TumongoModel};
 
 #[derive(TumongoModel, Debug, Serialize, Deserialize, Default)]
 #[tumongo(coll_name = "persons")] 
@@ -15,6 +15,9 @@ struct Person {
     pub cars: Vec<ObjectId>,
     pub created_at: DateTime,
     pub updated_at: tumongo::DateTime,
+    #[serde(skip)]
+    #[tumongo(snapshot)]
+    pub snapshot: tumongo::Snapshot,
 }
  
 #[derive(TumongoModel, Serialize, Deserialize)]
@@ -29,6 +32,13 @@ struct Car{
     pub updated_at: tumongo::DateTime,
 }
  
+#[derive(Projection, Debug, Deserialize)]
+#[tumongo(model = "Person")]
+struct PersonName {
+    pub id: Option<ObjectId>,
+    pub name: String,
+}
+
 #[tokio::main]
 async fn main() { 
     println!("Hello, world!"); 
@@ -49,9 +59,22 @@ async fn main() {
     tokio::time::sleep(tokio::time::Duration::from_millis(1500)).await;
     println!("Updating...");
     p.name = p.name;//format!("{}__updated", p.name);
+    println!("changed: {:?}", p.changed_fields());
     p.save(&db).await.expect("Failed to update");
     println!("{p:#?}");
 
+    let people = Person::query()
+        .filter(person_fields::company_id.eq(10))
+        .and(person_fields::name.ne("nobody"))
+        .sort(person_fields::created_at.desc())
+        .limit(20)
+        .find(&db)
+        .await
+        .expect("Failed to query");
+    println!("found {} people", people.len());
+    let names = Person::find_as::<PersonName>(&db, doc! {}).await.expect("Failed to project");
+    println!("{names:#?}");
+
 
 }
  
\ No newline at end of file